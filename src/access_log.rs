@@ -0,0 +1,516 @@
+use axum::{
+    body::{boxed, Bytes, HttpBody},
+    extract::State,
+    http::{header, HeaderMap, HeaderName, Request, Version},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use http_body::SizeHint;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{AccessLogConfig, ConfigError, LogFormat, OutputFormat},
+    proxies::ClientIp,
+    request_id::RequestId,
+    state::AppState,
+};
+
+// Lines waiting for the writer thread; more are dropped, and counted,
+// rather than hold up responses while the disk is slow
+const QUEUED_LINES: usize = 8192;
+
+// Writes one line per request in Common or Combined Log Format, or as JSON.
+// Lines are formatted by the request and written by a thread of their
+// own, so responses never wait on the log file
+#[derive(Debug)]
+pub struct AccessLog {
+    format: LogFormat,
+    output: OutputFormat,
+    log_duration: bool,
+    log_request_id: bool,
+    lines: SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Line(String),
+    // Answered once the lines sent before it are written
+    Flush(mpsc::Sender<()>),
+}
+
+impl AccessLog {
+    pub fn from_config(config: &AccessLogConfig, output: OutputFormat) -> Result<AccessLog, ConfigError> {
+        let mut writer = LogWriter {
+            path: config.path.clone(),
+            file: None,
+            size: 0,
+            max_size: config.max_size,
+            rotate_secs: config.rotate_secs,
+            period: 0,
+        };
+        writer.open().map_err(|err| match &config.path {
+            Some(path) => ConfigError::Io(path.clone(), err),
+            None => ConfigError::Invalid(err.to_string()),
+        })?;
+
+        let (lines, queued) = mpsc::sync_channel(QUEUED_LINES);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let counted = dropped.clone();
+        thread::Builder::new()
+            .name("access-log".into())
+            .spawn(move || write_lines(writer, queued, counted))
+            .map_err(|err| ConfigError::Invalid(format!("Cannot start the access log writer: {}", err)))?;
+
+        Ok(AccessLog {
+            format: config.format,
+            output,
+            log_duration: config.log_duration,
+            log_request_id: config.log_request_id,
+            lines,
+            dropped,
+        })
+    }
+
+    fn write(&self, entry: &Entry, status: u16, bytes: u64, aborted: bool) {
+        let line = match self.output {
+            OutputFormat::Text => self.clf_line(entry, status, bytes),
+            OutputFormat::Json => self.json_line(entry, status, bytes, aborted),
+        };
+        if let Err(TrySendError::Full(_)) = self.lines.try_send(Message::Line(line)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Wait until the lines logged so far are written, e.g. before exiting
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+        if self.lines.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv();
+        }
+    }
+
+    fn clf_line(&self, entry: &Entry, status: u16, bytes: u64) -> String {
+        let mut line = format!(
+            "{} - - [{}] \"{} {} {:?}\" {} {}",
+            entry.remote,
+            clf_time(entry.time),
+            entry.method,
+            escape(&entry.uri),
+            entry.version,
+            status,
+            if bytes == 0 { "-".to_string() } else { bytes.to_string() },
+        );
+        if self.format == LogFormat::Combined {
+            let field = |value: &Option<String>| value.as_deref().map_or("-".to_string(), escape);
+            line.push_str(&format!(" \"{}\" \"{}\"", field(&entry.referer), field(&entry.user_agent)));
+        }
+        if self.log_duration {
+            line.push_str(&format!(" {}", entry.started.elapsed().as_micros()));
+        }
+        if self.log_request_id {
+            line.push_str(&format!(" {}", entry.request_id.as_deref().unwrap_or("-")));
+        }
+        line.push('\n');
+        line
+    }
+
+    // Same fields under stable names; the format setting only decides
+    // whether Referer and User-Agent are included. Responses whose client
+    // went away before the body was sent in full are marked aborted
+    fn json_line(&self, entry: &Entry, status: u16, bytes: u64, aborted: bool) -> String {
+        let mut object = serde_json::json!({
+            "time": rfc3339_time(entry.time),
+            "remote": entry.remote.to_string(),
+            "method": entry.method,
+            "uri": entry.uri,
+            "protocol": format!("{:?}", entry.version),
+            "status": status,
+            "bytes": bytes,
+            "request_id": entry.request_id,
+        });
+        if self.format == LogFormat::Combined {
+            object["referer"] = entry.referer.clone().into();
+            object["user_agent"] = entry.user_agent.clone().into();
+        }
+        if self.log_duration {
+            object["duration_us"] = (entry.started.elapsed().as_micros() as u64).into();
+        }
+        if aborted {
+            object["aborted"] = true.into();
+        }
+        format!("{}\n", object)
+    }
+}
+
+// Writer thread, running until the log is dropped
+fn write_lines(mut writer: LogWriter, lines: mpsc::Receiver<Message>, dropped: Arc<AtomicU64>) {
+    for message in lines {
+        let line = match message {
+            Message::Line(line) => line,
+            Message::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+        let missed = dropped.swap(0, Ordering::Relaxed);
+        if missed > 0 {
+            tracing::warn!("Access log fell behind and dropped {} lines", missed);
+        }
+        if let Err(err) = writer.write(line.as_bytes()) {
+            tracing::warn!("Access log error: {}", err);
+        }
+    }
+}
+
+// Log destination, rotated by size and age when it is a file
+#[derive(Debug)]
+struct LogWriter {
+    // None for stdout
+    path: Option<PathBuf>,
+    file: Option<File>,
+    size: u64,
+    max_size: u64,
+    rotate_secs: u64,
+    // Rotation period the open file belongs to
+    period: u64,
+}
+
+impl LogWriter {
+    fn open(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        self.period = self.current_period();
+        Ok(())
+    }
+
+    fn current_period(&self) -> u64 {
+        match self.rotate_secs {
+            0 => 0,
+            secs => unix_secs(SystemTime::now()) / secs,
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return io::stdout().lock().write_all(line);
+        };
+
+        let too_big = self.max_size > 0 && self.size + line.len() as u64 > self.max_size && self.size > 0;
+        if self.file.is_some() && (too_big || self.current_period() != self.period) {
+            // Move the current file aside, named after the time it was rotated
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(format!(".{}", compact_time(SystemTime::now())));
+            match fs::rename(&path, rotated) {
+                Ok(()) => self.file = None,
+                // Someone moved or deleted it already; start a new one
+                Err(err) if err.kind() == io::ErrorKind::NotFound => self.file = None,
+                // Keep appending to the current file rather than losing
+                // lines, and try again after another max_size bytes or in
+                // the next period
+                Err(err) => {
+                    tracing::warn!("Cannot rotate the access log {}: {}", path.display(), err);
+                    self.size = 0;
+                    self.period = self.current_period();
+                }
+            }
+        }
+
+        if self.file.is_none() {
+            // Reopen after an earlier failure
+            self.open()?;
+        }
+        self.file.as_mut().unwrap().write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+}
+
+// What is logged about a request, captured before it is handled
+struct Entry {
+    remote: IpAddr,
+    time: SystemTime,
+    started: Instant,
+    method: String,
+    uri: String,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: Option<String>,
+}
+
+// Middleware logging every request once its response body is done
+pub async fn log_requests<B>(
+    State(state): State<Arc<AppState>>,
+    Extension(ClientIp(remote)): Extension<ClientIp>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.access_log.is_none() {
+        return next.run(req).await;
+    }
+
+    let entry = Entry {
+        remote,
+        time: SystemTime::now(),
+        started: Instant::now(),
+        method: req.method().to_string(),
+        uri: req.uri().to_string(),
+        version: req.version(),
+        referer: header_text(req.headers(), header::REFERER),
+        user_agent: header_text(req.headers(), header::USER_AGENT),
+        request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+    };
+
+    let response = next.run(req).await;
+    let status = response.status().as_u16();
+    // Streamed bodies only know their length from the header
+    let length = response.headers().get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse().ok());
+    response.map(|body| boxed(LoggedBody {
+        length: length.or(body.size_hint().exact()),
+        inner: body,
+        state,
+        entry: Some(entry),
+        status,
+        bytes: 0,
+        ended: false,
+    }))
+}
+
+// Response body that counts what it sends and logs the request when dropped
+struct LoggedBody<B: HttpBody> {
+    inner: B,
+    state: Arc<AppState>,
+    entry: Option<Entry>,
+    status: u16,
+    bytes: u64,
+    // Body length, when known up front; hyper stops reading a body once
+    // Content-Length bytes are sent
+    length: Option<u64>,
+    // Whether the body was read to its end
+    ended: bool,
+}
+
+impl<B: HttpBody> Drop for LoggedBody<B> {
+    fn drop(&mut self) {
+        if let (Some(log), Some(entry)) = (&self.state.access_log, self.entry.take()) {
+            let complete = self.ended
+                || self.inner.is_end_stream()
+                || self.length.is_some_and(|length| self.bytes >= length);
+            if !complete {
+                tracing::info!("{} {} aborted by the client after {} bytes", entry.method, entry.uri, self.bytes);
+            }
+            log.write(&entry, self.status, self.bytes, !complete);
+        }
+    }
+}
+
+impl<B> HttpBody for LoggedBody<B>
+where
+    B: HttpBody<Data = Bytes, Error = axum::Error> + Unpin,
+{
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(data))) => self.bytes += data.len() as u64,
+            Poll::Ready(None) => self.ended = true,
+            _ => {}
+        }
+        poll
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+fn header_text(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers.get(name).map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+// Quote characters that would break the quoted fields of a log line
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+// Calendar date and time of day in UTC
+pub fn utc(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let secs = unix_secs(time);
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+
+    // Days since 1970-01-01 to a proleptic Gregorian date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
+
+// "10/Oct/2000:13:55:36 +0000"
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year, hour, minute, second,
+    )
+}
+
+// "2000-10-10T13:55:36Z"
+pub fn rfc3339_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+// "20001010135536"
+fn compact_time(time: SystemTime) -> String {
+    let (year, month, day, hour, minute, second) = utc(time);
+    format!("{}{:02}{:02}{:02}{:02}{:02}", year, month, day, hour, minute, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn writer(path: &std::path::Path, max_size: u64) -> LogWriter {
+        let mut writer = LogWriter {
+            path: Some(path.to_path_buf()),
+            file: None,
+            size: 0,
+            max_size,
+            rotate_secs: 0,
+            period: 0,
+        };
+        writer.open().unwrap();
+        writer
+    }
+
+    fn rotated(dir: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("access.log."))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn files_are_rotated_by_size() {
+        let dir = scratch_dir("access-log-rotate");
+        let path = dir.join("access.log");
+        let mut writer = writer(&path, 10);
+        writer.write(b"first\n").unwrap();
+        writer.write(b"second\n").unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        let rotated = rotated(&dir);
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(fs::read_to_string(dir.join(&rotated[0])).unwrap(), "first\n");
+    }
+
+    #[test]
+    fn failed_rotations_keep_writing_to_the_current_file() {
+        let dir = scratch_dir("access-log-stuck");
+        let path = dir.join("access.log");
+        let mut writer = writer(&path, 10);
+        writer.write(b"first\n").unwrap();
+        // Directories in the way of every name the rotation could pick
+        let now = unix_secs(SystemTime::now());
+        for secs in now..now + 5 {
+            let mut name = path.clone().into_os_string();
+            name.push(format!(".{}", compact_time(UNIX_EPOCH + std::time::Duration::from_secs(secs))));
+            fs::create_dir_all(PathBuf::from(name).join("in-the-way")).unwrap();
+        }
+
+        writer.write(b"second\n").unwrap();
+        writer.write(b"third\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first\nsecond\nthird\n");
+    }
+
+    #[test]
+    fn deleted_files_are_recreated() {
+        let dir = scratch_dir("access-log-deleted");
+        let path = dir.join("access.log");
+        let mut writer = writer(&path, 10);
+        writer.write(b"first\n").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        writer.write(b"second\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+    }
+
+    #[test]
+    fn flushing_waits_for_queued_lines() {
+        let dir = scratch_dir("access-log-flush");
+        let config = AccessLogConfig {
+            enabled: true,
+            path: Some(dir.join("access.log")),
+            ..AccessLogConfig::default()
+        };
+        let log = AccessLog::from_config(&config, OutputFormat::Text).unwrap();
+        for _ in 0..100 {
+            log.lines.send(Message::Line("line\n".into())).unwrap();
+        }
+        log.flush();
+        assert_eq!(fs::read_to_string(dir.join("access.log")).unwrap(), "line\n".repeat(100));
+    }
+}
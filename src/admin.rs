@@ -0,0 +1,197 @@
+use axum::{
+    extract::State,
+    http::{header, Request, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use std::sync::{atomic::Ordering, Arc};
+
+use crate::{
+    config::{AdminConfig, Config},
+    error::AppError,
+    state::{AppState, Reloadable},
+};
+
+// Operator API under /._admin/, guarded by a bearer token
+#[derive(Debug)]
+pub struct Admin {
+    // None once a reload removed the token, which turns the API off
+    token: Reloadable<Option<String>>,
+    // Effective configuration, rendered when it is loaded
+    config: Reloadable<serde_json::Value>,
+}
+
+impl Admin {
+    // None unless a token is configured
+    pub fn from_config(admin: &AdminConfig, config: &Config) -> Option<Admin> {
+        let token = admin.enabled_token()?;
+        Some(Admin {
+            token: Reloadable::new(Some(token.to_string())),
+            config: Reloadable::new(serde_json::to_value(config).unwrap()),
+        })
+    }
+
+    // A changed token replaces the old one right away
+    pub fn reload(&self, config: &Config) {
+        self.token.set(config.admin.enabled_token().map(str::to_string));
+        self.config.set(serde_json::to_value(config).unwrap());
+    }
+}
+
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/._admin/config", get(config))
+        .route("/._admin/connections", get(connections))
+        .route("/._admin/uploads", get(uploads))
+        .route("/._admin/drain", post(start_draining).delete(stop_draining))
+        .route_layer(middleware::from_fn_with_state(state, authorize))
+}
+
+async fn authorize<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let token = state.admin.as_ref().and_then(|admin| admin.token.get().as_ref().clone());
+    let Some(token) = token else {
+        return AppError::NotFound(req.uri().path().to_string()).into_response();
+    };
+
+    let presented = req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !presented.is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes())) {
+        let mut response = AppError::Rejected(
+            StatusCode::UNAUTHORIZED,
+            "Admin token required".into(),
+        ).into_response();
+        response.headers_mut().insert(header::WWW_AUTHENTICATE, "Bearer".parse().unwrap());
+        return response;
+    }
+
+    next.run(req).await
+}
+
+async fn config(State(state): State<Arc<AppState>>) -> Response {
+    match &state.admin {
+        Some(admin) => Json(admin.config.get().as_ref().clone()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn connections(State(state): State<Arc<AppState>>) -> Response {
+    let connections = state.connections.as_ref().map(|connections| connections.list());
+    Json(connections.unwrap_or_default()).into_response()
+}
+
+async fn uploads(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let uploads = match &state.tus {
+        Some(tus) => tus.uploads().await?,
+        None => Vec::new(),
+    };
+    Ok(Json(uploads).into_response())
+}
+
+// Close every connection after its current request and fail readiness
+// probes, so a load balancer moves clients elsewhere before a restart
+async fn start_draining(State(state): State<Arc<AppState>>) -> StatusCode {
+    state.draining.store(true, Ordering::Relaxed);
+    tracing::info!("Draining connections");
+    StatusCode::NO_CONTENT
+}
+
+async fn stop_draining(State(state): State<Arc<AppState>>) -> StatusCode {
+    state.draining.store(false, Ordering::Relaxed);
+    tracing::info!("No longer draining connections");
+    StatusCode::NO_CONTENT
+}
+
+// Compare without leaking where the first difference is
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn config(token: Option<&str>) -> Config {
+        Config {
+            admin: AdminConfig { token: token.map(str::to_string) },
+            ..Config::default()
+        }
+    }
+
+    async fn status(state: &Arc<AppState>, token: &str) -> StatusCode {
+        let req = Request::get("/._admin/uploads")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let app = routes(state.clone()).with_state(state.clone());
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn reloading_replaces_the_token() {
+        let state = Arc::new(AppState::from_config(&config(Some("old"))).unwrap());
+        assert_eq!(status(&state, "old").await, StatusCode::OK);
+
+        state.reload(&config(Some("new"))).unwrap();
+        assert_eq!(status(&state, "old").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&state, "new").await, StatusCode::OK);
+
+        state.reload(&config(None)).unwrap();
+        assert_eq!(status(&state, "new").await, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn only_enabling_the_api_needs_a_restart() {
+        assert_eq!(config(Some("token")).restart_required(&config(None)), ["admin"]);
+        assert!(config(Some("new")).restart_required(&config(Some("old"))).is_empty());
+        assert!(config(None).restart_required(&config(Some("old"))).is_empty());
+    }
+
+    #[tokio::test]
+    async fn drain_mode_is_switched_on_and_off() {
+        let state = Arc::new(AppState::from_config(&config(Some("token"))).unwrap());
+        for (method, draining) in [("POST", true), ("DELETE", false)] {
+            let req = Request::builder().method(method).uri("/._admin/drain")
+                .header(header::AUTHORIZATION, "Bearer token")
+                .body(Body::empty())
+                .unwrap();
+            let response = routes(state.clone()).with_state(state.clone()).oneshot(req).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+            assert_eq!(state.draining.load(Ordering::Relaxed), draining);
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_need_the_bearer_token() {
+        let state = Arc::new(AppState::from_config(&config(Some("secret"))).unwrap());
+        assert_eq!(status(&state, "secret").await, StatusCode::OK);
+        assert_eq!(status(&state, "secret2").await, StatusCode::UNAUTHORIZED);
+
+        let req = Request::get("/._admin/config").body(Body::empty()).unwrap();
+        let response = routes(state.clone()).with_state(state.clone()).oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn the_api_is_hidden_without_a_token() {
+        let state = Arc::new(AppState::from_config(&config(None)).unwrap());
+        assert_eq!(status(&state, "anything").await, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn tokens_are_compared_whole() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
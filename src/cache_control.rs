@@ -0,0 +1,110 @@
+use axum::http::HeaderValue;
+use globset::GlobSet;
+use std::path::Path;
+
+use crate::{
+    config::{CacheControlRule, ConfigError},
+    paths,
+};
+
+// Cache-Control values for served files, chosen by path
+#[derive(Debug)]
+pub struct CacheControl {
+    paths: GlobSet,
+    // Header value of each rule, in the order of `paths`
+    values: Vec<HeaderValue>,
+}
+
+impl CacheControl {
+    pub fn from_config(rules: &[CacheControlRule]) -> Result<CacheControl, ConfigError> {
+        let patterns = rules.iter().map(|rule| rule.path.clone()).collect::<Vec<_>>();
+        let values = rules.iter().map(header_value).collect::<Result<_, _>>()?;
+
+        Ok(CacheControl {
+            paths: paths::build_globs(&patterns)?,
+            values,
+        })
+    }
+
+    // Value of the first rule matching the path, if any
+    pub fn header_for(&self, path: &Path) -> Option<HeaderValue> {
+        let first = self.paths.matches(paths::glob_path(path)).into_iter().min()?;
+        Some(self.values[first].clone())
+    }
+}
+
+fn header_value(rule: &CacheControlRule) -> Result<HeaderValue, ConfigError> {
+    let mut directives = Vec::new();
+    if rule.no_store {
+        directives.push("no-store".to_string());
+    }
+    if rule.no_cache {
+        directives.push("no-cache".to_string());
+    }
+    if let Some(max_age) = rule.max_age_secs {
+        directives.push(format!("max-age={}", max_age));
+    }
+    if rule.immutable {
+        directives.push("immutable".to_string());
+    }
+
+    if directives.is_empty() {
+        return Err(ConfigError::Invalid(format!(
+            "cache_control rule for {:?} sets no directive", rule.path,
+        )));
+    }
+    Ok(HeaderValue::from_str(&directives.join(", ")).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(path: &str) -> CacheControlRule {
+        CacheControlRule {
+            path: path.to_string(),
+            max_age_secs: None,
+            immutable: false,
+            no_cache: false,
+            no_store: false,
+        }
+    }
+
+    fn header_for(cache_control: &CacheControl, path: &str) -> Option<String> {
+        cache_control.header_for(Path::new(path)).map(|value| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn the_first_matching_rule_applies() {
+        let cache_control = CacheControl::from_config(&[
+            CacheControlRule { no_cache: true, ..rule("**/*.html") },
+            CacheControlRule { max_age_secs: Some(31536000), immutable: true, ..rule("assets/**") },
+            CacheControlRule { max_age_secs: Some(60), ..rule("**") },
+        ]).unwrap();
+
+        assert_eq!(header_for(&cache_control, "assets/index.html").as_deref(), Some("no-cache"));
+        assert_eq!(header_for(&cache_control, "assets/app.js").as_deref(), Some("max-age=31536000, immutable"));
+        assert_eq!(header_for(&cache_control, "./docs/a.txt").as_deref(), Some("max-age=60"));
+        assert_eq!(header_for(&CacheControl::from_config(&[]).unwrap(), "a.txt"), None);
+    }
+
+    #[test]
+    fn directives_combine_in_a_fixed_order() {
+        let all = CacheControlRule {
+            max_age_secs: Some(0),
+            immutable: true,
+            no_cache: true,
+            no_store: true,
+            ..rule("**")
+        };
+        let cache_control = CacheControl::from_config(&[all]).unwrap();
+        assert_eq!(header_for(&cache_control, "a").as_deref(), Some("no-store, no-cache, max-age=0, immutable"));
+    }
+
+    #[test]
+    fn rules_need_a_directive_and_a_valid_glob() {
+        assert!(matches!(CacheControl::from_config(&[rule("**")]), Err(ConfigError::Invalid(_))));
+        let bad_glob = CacheControlRule { no_store: true, ..rule("[") };
+        assert!(CacheControl::from_config(&[bad_glob]).is_err());
+    }
+}
@@ -0,0 +1,958 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeMap, HashMap}, fmt, fs, io, net::SocketAddr, path::{Path, PathBuf}, time::Duration};
+
+// Largest file that may be memory-mapped; bigger files stream just as well
+const MMAP_MAX_SIZE_CAP: u64 = 64 << 20;
+
+// Top-level server configuration, loaded from a TOML file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub errors: ErrorConfig,
+    pub mime: MimeConfig,
+    pub paths: PathConfig,
+    pub tus: TusConfig,
+    pub digest: DigestConfig,
+    pub timeouts: TimeoutConfig,
+    pub limits: LimitConfig,
+    pub rate_limit: RateLimitConfig,
+    pub bandwidth: BandwidthConfig,
+    pub socket: SocketConfig,
+    pub files: FileConfig,
+    pub cache_control: Vec<CacheControlRule>,
+    pub headers: Vec<HeaderRule>,
+    pub access_log: AccessLogConfig,
+    pub logging: LoggingConfig,
+    pub otel: OtelConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub proxies: ProxyConfig,
+    pub admin: AdminConfig,
+    pub shutdown: ShutdownConfig,
+    pub cors: CorsConfig,
+    pub security_headers: SecurityHeaderConfig,
+    pub archive: ArchiveConfig,
+    pub thumbnails: ThumbnailConfig,
+    pub form_upload: FormUploadConfig,
+    pub events: EventConfig,
+    pub watch: WatchConfig,
+    pub search: SearchConfig,
+    pub uploads: UploadConfig,
+    pub etag: EtagConfig,
+    pub runtime: RuntimeConfig,
+}
+
+// How error responses are rendered
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorConfig {
+    pub format: ErrorFormat,
+    // Optional HTML page with {{status}}, {{reason}}, {{message}},
+    // {{request_id}}, {{request_id_label}} and {{lang}} placeholders
+    pub html_template: Option<PathBuf>,
+    // Directory of <language>.toml message catalogs for HTML error pages
+    pub catalog_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorFormat {
+    // Pick a format from the request's Accept header
+    #[default]
+    Negotiate,
+    Text,
+    Html,
+    Xml,
+    Json,
+}
+
+// Extension to MIME type overrides applied on top of mime_guess
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MimeConfig {
+    // File in mime.types format
+    pub types_file: Option<PathBuf>,
+    // Extension (without the dot) to MIME type
+    pub types: HashMap<String, String>,
+    // Charset appended to text/* types; empty to leave them bare
+    pub charset: String,
+    // Extension (without the dot) to charset, overriding `charset`
+    pub charsets: HashMap<String, String>,
+    // MIME types ("text/html", "image/*") always served as attachments
+    pub attachment_types: Vec<String>,
+    // Extensions (without the dot) always served as attachments
+    pub attachment_extensions: Vec<String>,
+}
+
+impl Default for MimeConfig {
+    fn default() -> Self {
+        MimeConfig {
+            types_file: None,
+            types: HashMap::new(),
+            charset: "utf-8".to_string(),
+            charsets: HashMap::new(),
+            attachment_types: Vec::new(),
+            attachment_extensions: Vec::new(),
+        }
+    }
+}
+
+// Rules applied when mapping request paths to files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathConfig {
+    pub symlinks: SymlinkPolicy,
+    // Answer 404 for dotfiles and, on Windows, files with the hidden attribute
+    pub hide_hidden: bool,
+    // Globs matched against the request path; denied paths are never served
+    pub deny: Vec<String>,
+    // When not empty, only paths matching one of these globs are served
+    pub allow: Vec<String>,
+    pub deny_status: DenyStatus,
+    pub unicode_normalization: UnicodeNormalization,
+    // Match request paths against file names ignoring case
+    pub case_insensitive: bool,
+    // File names accepted for files the server creates
+    pub new_names: NamePolicy,
+    // Globs naming upload-only directories: files can be uploaded into
+    // them, but they and everything below them cannot be read or listed
+    pub drop_folders: Vec<String>,
+}
+
+// Which names writes may create; the stricter policies keep a share usable
+// from every platform
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NamePolicy {
+    // Whatever the filesystem accepts
+    Any,
+    // No control characters, NUL included
+    #[default]
+    NoControl,
+    // Also none of Windows' reserved names and characters, and no trailing
+    // dot or space
+    Portable,
+}
+
+// Unicode normalization form applied to request paths and file name lookups
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeNormalization {
+    #[default]
+    None,
+    Nfc,
+    Nfd,
+}
+
+// Status returned for paths rejected by the deny/allow lists
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DenyStatus {
+    #[default]
+    NotFound,
+    Forbidden,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    // Never serve through a symlink
+    Deny,
+    // Follow symlinks whose target stays inside the served directory
+    #[default]
+    InsideRoot,
+    // Follow symlinks wherever they point
+    Follow,
+}
+
+// Resumable uploads over the tus protocol, served under /.tus
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TusConfig {
+    pub enabled: bool,
+    // Largest upload accepted, in bytes
+    pub max_size: Option<u64>,
+}
+
+// File digests sent in answer to Want-Digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DigestConfig {
+    // Larger files are served without a Digest header
+    pub max_file_size: u64,
+    // Number of computed digests to remember
+    pub cache_entries: usize,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        DigestConfig {
+            max_file_size: 64 << 20,
+            cache_entries: 1024,
+        }
+    }
+}
+
+// Per-phase timeouts, in seconds; 0 disables a timeout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    // Longest wait for the client to send anything while no response is
+    // in progress, e.g. the next request on a kept-alive connection
+    pub idle_secs: u64,
+    // Time allowed on a new connection before the first byte of a request
+    pub request_start_secs: u64,
+    // Time allowed for a client to send the request line and headers
+    pub header_read_secs: u64,
+    // Longest stall allowed while reading a request body
+    pub body_read_secs: u64,
+    // Longest stall allowed while the client is not accepting response data
+    pub body_write_secs: u64,
+    // Deadline for a whole request, including its body transfer
+    pub request_secs: u64,
+    // request_secs for particular methods, by method name
+    pub methods: BTreeMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            idle_secs: 120,
+            request_start_secs: 10,
+            header_read_secs: 30,
+            body_read_secs: 60,
+            body_write_secs: 60,
+            request_secs: 0,
+            methods: BTreeMap::new(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn idle(&self) -> Option<Duration> {
+        seconds(self.idle_secs)
+    }
+
+    pub fn request_start(&self) -> Option<Duration> {
+        seconds(self.request_start_secs)
+    }
+
+    pub fn header_read(&self) -> Option<Duration> {
+        seconds(self.header_read_secs)
+    }
+
+    pub fn body_read(&self) -> Option<Duration> {
+        seconds(self.body_read_secs)
+    }
+
+    pub fn body_write(&self) -> Option<Duration> {
+        seconds(self.body_write_secs)
+    }
+
+    pub fn request(&self) -> Option<Duration> {
+        seconds(self.request_secs)
+    }
+}
+
+// Limits on what a single request may send
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitConfig {
+    // Largest request head (request line plus headers) hyper will buffer;
+    // at least 8192
+    pub max_header_bytes: usize,
+    // Most header fields a request may carry; at most 100, hyper's own cap
+    pub max_headers: usize,
+    // Requests in flight at once, overall and per cost class (cheap:
+    // metadata, expensive: content transfers or builds); 0 means unlimited
+    pub max_requests: usize,
+    pub max_cheap_requests: usize,
+    pub max_expensive_requests: usize,
+    // Retry-After sent with 503 responses when a limit is reached
+    pub retry_after_secs: u64,
+    // How long a request may wait for a free slot before being shed, in
+    // milliseconds; 0 sheds right away
+    pub queue_wait_ms: u64,
+    // Requests waiting at once; more are shed right away
+    pub max_queued: usize,
+    // Connections are closed after a response once they are this many
+    // seconds old, or have served this many requests; 0 means no limit
+    pub max_connection_age_secs: u64,
+    pub max_connection_requests: u64,
+    // Directory entries one archive download or search may visit; 0 means
+    // unlimited
+    pub max_walk_entries: usize,
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        LimitConfig {
+            max_header_bytes: 65536,
+            max_headers: 100,
+            max_requests: 0,
+            max_cheap_requests: 0,
+            max_expensive_requests: 0,
+            retry_after_secs: 5,
+            queue_wait_ms: 0,
+            max_queued: 100,
+            max_connection_age_secs: 0,
+            max_connection_requests: 0,
+            max_walk_entries: 100000,
+        }
+    }
+}
+
+// Per-client-IP request budgets; a rate of 0 disables the budget
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    // Sustained requests per second for GET, HEAD and OPTIONS
+    pub read_per_sec: f64,
+    // Requests a client may make in a burst before being slowed down
+    pub read_burst: f64,
+    // The same for every other method
+    pub write_per_sec: f64,
+    pub write_burst: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            read_per_sec: 0.0,
+            read_burst: 50.0,
+            write_per_sec: 0.0,
+            write_burst: 10.0,
+        }
+    }
+}
+
+// Transfer rate caps in bytes per second, for all connections together and
+// for each one; 0 means unlimited
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BandwidthConfig {
+    // Data sent to clients: response bodies and headers
+    pub download_bytes_per_sec: u64,
+    pub connection_download_bytes_per_sec: u64,
+    // Data received from clients: request bodies and headers
+    pub upload_bytes_per_sec: u64,
+    pub connection_upload_bytes_per_sec: u64,
+}
+
+// Options of the listening socket and the connections it accepts
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    // Send small writes right away instead of coalescing them (TCP_NODELAY)
+    pub nodelay: bool,
+    // Idle time before TCP keepalive probes start; 0 disables keepalive
+    pub keepalive_secs: u64,
+    // Time between keepalive probes, and how many go unanswered before the
+    // connection is dropped; 0 keeps the system default
+    pub keepalive_interval_secs: u64,
+    pub keepalive_retries: u32,
+    // Connections the kernel queues while waiting to be accepted
+    pub backlog: u32,
+    // Let several processes bind the same address (SO_REUSEPORT, Unix only)
+    pub reuse_port: bool,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        SocketConfig {
+            nodelay: true,
+            keepalive_secs: 0,
+            keepalive_interval_secs: 0,
+            keepalive_retries: 0,
+            backlog: 1024,
+            reuse_port: false,
+        }
+    }
+}
+
+impl SocketConfig {
+    pub fn keepalive(&self) -> Option<Duration> {
+        seconds(self.keepalive_secs)
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        seconds(self.keepalive_interval_secs)
+    }
+
+    pub fn keepalive_retries(&self) -> Option<u32> {
+        (self.keepalive_retries > 0).then_some(self.keepalive_retries)
+    }
+}
+
+// How file contents are read for GET responses
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    // Read-only files up to this size are memory-mapped instead of read
+    // through a buffer; 0 disables mapping. A mapped file truncated while
+    // it is being sent crashes the server, so files anyone may write to
+    // are never mapped, and this is only for content made read-only
+    pub mmap_max_size: u64,
+    // Bytes read from disk at a time
+    pub read_buffer_size: usize,
+    // Largest piece of body handed to the connection at a time
+    pub chunk_size: usize,
+    // In-memory cache for frequently requested small files: total bytes
+    // held (0 disables it), files held, and largest file cached
+    pub cache_max_bytes: usize,
+    pub cache_entries: usize,
+    pub cache_max_file_size: u64,
+    // How long file metadata is remembered, in milliseconds (0 disables
+    // the stat cache), and for how many paths
+    pub stat_cache_ttl_ms: u64,
+    pub stat_cache_entries: usize,
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        FileConfig {
+            mmap_max_size: 0,
+            read_buffer_size: 65536,
+            chunk_size: 65536,
+            cache_max_bytes: 0,
+            cache_entries: 1024,
+            cache_max_file_size: 1 << 20,
+            stat_cache_ttl_ms: 0,
+            stat_cache_entries: 10_000,
+        }
+    }
+}
+
+// Cache-Control sent with files matching a glob; the first matching rule
+// applies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheControlRule {
+    pub path: String,
+    pub max_age_secs: Option<u64>,
+    #[serde(default)]
+    pub immutable: bool,
+    // Revalidate before every use
+    #[serde(default)]
+    pub no_cache: bool,
+    // Never store the response
+    #[serde(default)]
+    pub no_store: bool,
+}
+
+// Extra headers sent with files whose path matches a glob; every matching
+// rule applies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeaderRule {
+    pub path: String,
+    // Header names and values, replacing any the server would send except
+    // those describing the body and its version
+    pub set: BTreeMap<String, String>,
+}
+
+// One line per request, written once its response is complete
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    pub format: LogFormat,
+    // Append the time taken, in microseconds, to each line
+    pub log_duration: bool,
+    // Then the request ID; JSON lines always carry it
+    pub log_request_id: bool,
+    // File to append to; stdout when not set
+    pub path: Option<PathBuf>,
+    // Rotate the file once it would grow past this many bytes, and after
+    // this many seconds; 0 disables either
+    pub max_size: u64,
+    pub rotate_secs: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Common,
+    // Common plus the Referer and User-Agent
+    #[default]
+    Combined,
+}
+
+// Diagnostic logging, separate from the access log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // Filter in RUST_LOG syntax, e.g. "info" or "warn,axum_webdav=debug"
+    pub filter: String,
+    // Applies to the access log too
+    pub format: OutputFormat,
+    // Warn about requests taking longer than this, in milliseconds; 0
+    // disables the warning
+    pub slow_request_ms: u64,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            filter: "info".to_string(),
+            format: OutputFormat::Text,
+            slow_request_ms: 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    // One JSON object per line
+    Json,
+}
+
+// OpenTelemetry export of request spans; needs a build with the otel feature
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtelConfig {
+    pub enabled: bool,
+    // OTLP/gRPC collector
+    pub endpoint: String,
+    // Share of new traces recorded, from 0 to 1; requests carrying a
+    // traceparent follow the caller's decision
+    pub sampling_ratio: f64,
+    pub service_name: String,
+}
+
+impl Default for OtelConfig {
+    fn default() -> Self {
+        OtelConfig {
+            enabled: false,
+            endpoint: "http://localhost:4317".to_string(),
+            sampling_ratio: 1.0,
+            service_name: "axum-webdav".to_string(),
+        }
+    }
+}
+
+// Prometheus metrics at /metrics
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    // Serve metrics on this address only, instead of next to the files
+    pub bind: Option<SocketAddr>,
+}
+
+// Probe endpoints /healthz (alive) and /readyz (served directory usable)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    pub enabled: bool,
+}
+
+// Reverse proxies in front of the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    // Addresses or CIDR blocks whose X-Request-Id, X-Forwarded-For and
+    // Forwarded headers are believed
+    pub trusted: Vec<String>,
+}
+
+// Operator API under /._admin/
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    // Bearer token required by the API; the API is off without one
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+}
+
+impl AdminConfig {
+    // The token, unless the API is off
+    pub fn enabled_token(&self) -> Option<&str> {
+        self.token.as_deref().filter(|token| !token.is_empty())
+    }
+}
+
+// What happens between a shutdown signal and exit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    // How long requests in flight may take to finish; 0 waits for them
+    // however long they take
+    pub grace_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig { grace_secs: 30 }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn grace(&self) -> Option<Duration> {
+        seconds(self.grace_secs)
+    }
+}
+
+// Cross-origin access for browser-based clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    pub enabled: bool,
+    // Origins such as "https://app.example.com"; "*" allows any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    // Request headers scripts may send; "*" allows any
+    pub allowed_headers: Vec<String>,
+    // Response headers scripts may read
+    pub exposed_headers: Vec<String>,
+    // Let scripts send cookies and credentials; needs explicit origins
+    // and headers
+    pub allow_credentials: bool,
+    // How long browsers may cache a preflight answer; 0 omits the header
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        let strings = |list: &[&str]| list.iter().map(|item| item.to_string()).collect();
+        CorsConfig {
+            enabled: false,
+            allowed_origins: Vec::new(),
+            allowed_methods: strings(&[
+                "GET", "HEAD", "OPTIONS", "PUT", "POST", "PATCH", "DELETE", "PROPFIND",
+                "PROPPATCH", "MKCOL", "COPY", "MOVE", "LOCK", "UNLOCK",
+            ]),
+            allowed_headers: strings(&[
+                "Authorization", "Content-Type", "Depth", "Destination", "Overwrite",
+                "Lock-Token", "If", "Timeout", "Want-Digest", "Tus-Resumable",
+                "Upload-Length", "Upload-Offset", "Upload-Metadata", "Upload-Checksum",
+                "X-Request-Id",
+            ]),
+            exposed_headers: strings(&[
+                "DAV", "ETag", "Location", "Lock-Token", "Digest", "Tus-Resumable",
+                "Tus-Version", "Upload-Length", "Upload-Offset", "X-Request-Id",
+            ]),
+            allow_credentials: false,
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn max_age(&self) -> Option<Duration> {
+        seconds(self.max_age_secs)
+    }
+}
+
+// Hardening headers for servers exposed to browsers directly
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeaderConfig {
+    // Strict-Transport-Security; 0 omits it. Only send it when clients
+    // reach the server over HTTPS
+    pub hsts_max_age_secs: u64,
+    pub hsts_include_subdomains: bool,
+    pub hsts_preload: bool,
+    // X-Content-Type-Options: nosniff
+    pub content_type_options: bool,
+    // Referrer-Policy; empty omits it
+    pub referrer_policy: String,
+    // Content-Security-Policy for pages the server generates, not for
+    // served files; empty omits it
+    pub content_security_policy: String,
+}
+
+impl Default for SecurityHeaderConfig {
+    fn default() -> Self {
+        SecurityHeaderConfig {
+            hsts_max_age_secs: 0,
+            hsts_include_subdomains: false,
+            hsts_preload: false,
+            content_type_options: true,
+            referrer_policy: "strict-origin-when-cross-origin".into(),
+            content_security_policy: "default-src 'none'; style-src 'unsafe-inline'; img-src 'self'".into(),
+        }
+    }
+}
+
+// Directory downloads as ZIP or tar.gz archives
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    pub enabled: bool,
+    pub zip_compression: ZipCompression,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZipCompression {
+    // Entries as they are, for content that is compressed already
+    Store,
+    #[default]
+    Deflate,
+}
+
+// Image previews served for ?thumb=N
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    pub enabled: bool,
+    // Where generated thumbnails are kept; defaults to the system temp dir
+    pub cache_dir: Option<PathBuf>,
+    // Bytes of thumbnails kept, least recently used deleted first; 0 means
+    // unlimited
+    pub cache_max_bytes: u64,
+    // Images decoded at once; 0 means unlimited
+    pub max_decodes: usize,
+    // Largest edge length clients may ask for, in pixels
+    pub max_size: u32,
+    // Larger images are not decoded, in bytes
+    pub max_source_size: u64,
+    // JPEG quality, from 1 to 100
+    pub quality: u8,
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        ThumbnailConfig {
+            enabled: false,
+            cache_dir: None,
+            cache_max_bytes: 512 * 1024 * 1024,
+            max_decodes: 4,
+            max_size: 1024,
+            max_source_size: 50 * 1024 * 1024,
+            quality: 80,
+        }
+    }
+}
+
+// Uploads from HTML forms: POST multipart/form-data to a directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormUploadConfig {
+    pub enabled: bool,
+    // Largest file accepted, in bytes; 0 means unlimited
+    pub max_file_size: u64,
+    // Most files accepted in one request
+    pub max_files: usize,
+}
+
+impl Default for FormUploadConfig {
+    fn default() -> Self {
+        FormUploadConfig {
+            enabled: false,
+            max_file_size: 1 << 30,
+            max_files: 100,
+        }
+    }
+}
+
+// Change notifications streamed as server-sent events from /.events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventConfig {
+    pub enabled: bool,
+    // Changes a subscriber may fall behind by before it misses some
+    pub buffer: usize,
+    // Seconds between comments keeping idle streams open
+    pub keep_alive_secs: u64,
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        EventConfig {
+            enabled: false,
+            buffer: 1024,
+            keep_alive_secs: 15,
+        }
+    }
+}
+
+// Watching the served directory for changes made outside the server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    pub enabled: bool,
+}
+
+// File name search at /.search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SearchConfig {
+    pub enabled: bool,
+    // Most matches returned by one search
+    pub max_results: usize,
+    // Directory levels searched, counting the one searched from
+    pub max_depth: usize,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            enabled: false,
+            max_results: 1000,
+            max_depth: 16,
+        }
+    }
+}
+
+// How tus and form uploads are written to disk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    // Leave blocks of zeros unallocated instead of writing them
+    pub sparse: bool,
+    // Reserve the space of uploads announcing their length up front
+    pub preallocate: bool,
+    // Where uploads in progress are kept; must be on the same filesystem
+    // as the served directory
+    pub staging_dir: Option<PathBuf>,
+    // Free space tus uploads may not take, in bytes
+    pub reserve_bytes: u64,
+}
+
+// How ETags are made for files
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EtagConfig {
+    pub mode: EtagMode,
+    // Keep content hashes in an extended attribute of each file, so they
+    // survive restarts. Tags read back from there are weak, since anyone
+    // able to write the file can set the attribute
+    pub store_hashes: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    // From the device, inode, modification time and size
+    #[default]
+    Fast,
+    // From a SHA-256 of the content, computed when first needed
+    Content,
+    Off,
+}
+
+// How the async runtime is sized, and how many files may be open at once
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    // Threads running request handlers; 0 means one per CPU core
+    pub worker_threads: usize,
+    // Most threads doing blocking filesystem calls at once
+    pub max_blocking_threads: usize,
+    // Files held open at once for serving, hashing, archiving and uploads;
+    // requests past it wait for one to be closed. 0 means unlimited
+    pub max_open_files: usize,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        RuntimeConfig {
+            worker_threads: 0,
+            max_blocking_threads: 512,
+            max_open_files: 0,
+        }
+    }
+}
+
+fn seconds(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) =>
+                write!(f, "failed to read {}: {}", path.display(), err),
+            ConfigError::Parse(path, err) =>
+                write!(f, "failed to parse {}: {}", path.display(), err),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Config {
+    // Load the config file, or fall back to defaults when none is given
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+
+        let text = fs::read_to_string(path)
+            .map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+        let config: Config = toml::from_str(&text)
+            .map_err(|err| ConfigError::Parse(path.to_path_buf(), err))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Sections that differ from `running` but only take effect on restart;
+    // see AppState::reload for what is applied at runtime
+    pub fn restart_required(&self, running: &Config) -> Vec<String> {
+        let (serde_json::Value::Object(mut new), serde_json::Value::Object(mut old)) =
+            (serde_json::to_value(self).unwrap(), serde_json::to_value(running).unwrap())
+        else {
+            unreachable!("Config serializes to a map");
+        };
+        for section in [&mut new, &mut old] {
+            for reloadable in ["errors", "mime", "paths", "cache_control", "headers"] {
+                section.remove(reloadable);
+            }
+            if let Some(serde_json::Value::Object(logging)) = section.get_mut("logging") {
+                logging.remove("filter");
+            }
+        }
+
+        let mut changed: Vec<String> = new.into_iter()
+            .filter(|(name, value)| old.get(name) != Some(value))
+            .map(|(name, _)| name)
+            .collect();
+        // The admin token is never serialized. A new or removed token is
+        // applied on reload, but the API's routes only exist if it started
+        // with one
+        if self.admin.enabled_token().is_some() && running.admin.enabled_token().is_none() {
+            changed.push("admin".into());
+        }
+        changed
+    }
+
+    // Reject values the server cannot honor
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.limits.max_header_bytes < 8192 {
+            return Err(ConfigError::Invalid("limits.max_header_bytes must be at least 8192".into()));
+        }
+        if self.files.mmap_max_size > MMAP_MAX_SIZE_CAP {
+            return Err(ConfigError::Invalid(format!(
+                "files.mmap_max_size cannot exceed {} bytes", MMAP_MAX_SIZE_CAP,
+            )));
+        }
+        if self.files.read_buffer_size == 0 || self.files.chunk_size == 0 {
+            return Err(ConfigError::Invalid("files.read_buffer_size and files.chunk_size must be positive".into()));
+        }
+        if !(0.0..=1.0).contains(&self.otel.sampling_ratio) {
+            return Err(ConfigError::Invalid("otel.sampling_ratio must be between 0 and 1".into()));
+        }
+        if self.limits.max_headers > 100 {
+            return Err(ConfigError::Invalid("limits.max_headers cannot exceed 100".into()));
+        }
+        if !(1..=100).contains(&self.thumbnails.quality) {
+            return Err(ConfigError::Invalid("thumbnails.quality must be between 1 and 100".into()));
+        }
+        if self.runtime.max_blocking_threads == 0 {
+            return Err(ConfigError::Invalid("runtime.max_blocking_threads must be positive".into()));
+        }
+        Ok(())
+    }
+}
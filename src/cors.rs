@@ -0,0 +1,137 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use tower_http::cors::{AllowHeaders, AllowOrigin, CorsLayer};
+
+use crate::config::{ConfigError, CorsConfig};
+
+// Build the CORS layer, or None when cross-origin access is off
+pub fn layer(config: &CorsConfig) -> Result<Option<CorsLayer>, ConfigError> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let any_origin = config.allowed_origins.iter().any(|origin| origin == "*");
+    let any_header = config.allowed_headers.iter().any(|name| name == "*");
+    // Browsers refuse wildcards on credentialed requests anyway
+    if config.allow_credentials && (any_origin || any_header) {
+        return Err(ConfigError::Invalid(
+            "cors.allow_credentials needs explicit allowed_origins and allowed_headers".into(),
+        ));
+    }
+
+    let origins = match any_origin {
+        true => AllowOrigin::any(),
+        false => AllowOrigin::list(parse_all(&config.allowed_origins, "allowed_origins", |origin| {
+            HeaderValue::from_str(origin).ok()
+        })?),
+    };
+    let headers = match any_header {
+        true => AllowHeaders::any(),
+        false => AllowHeaders::list(parse_all(&config.allowed_headers, "allowed_headers", header_name)?),
+    };
+    let methods = parse_all(&config.allowed_methods, "allowed_methods", |method| {
+        Method::from_bytes(method.as_bytes()).ok()
+    })?;
+    let exposed = parse_all(&config.exposed_headers, "exposed_headers", header_name)?;
+
+    let mut layer = CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .expose_headers(exposed)
+        .allow_credentials(config.allow_credentials);
+    if let Some(max_age) = config.max_age() {
+        layer = layer.max_age(max_age);
+    }
+    Ok(Some(layer))
+}
+
+fn header_name(name: &str) -> Option<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes()).ok()
+}
+
+fn parse_all<T>(
+    items: &[String],
+    setting: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Vec<T>, ConfigError> {
+    items.iter()
+        .map(|item| parse(item).ok_or_else(|| {
+            ConfigError::Invalid(format!("cors.{}: invalid entry {:?}", setting, item))
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        response::Response,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app(config: CorsConfig) -> Router {
+        let layer = layer(&CorsConfig { enabled: true, ..config }).unwrap().unwrap();
+        Router::new().route("/a.txt", get(|| async { "a" })).layer(layer)
+    }
+
+    fn allowing(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allowed_origins: origins.iter().map(|origin| origin.to_string()).collect(),
+            max_age_secs: 600,
+            ..CorsConfig::default()
+        }
+    }
+
+    async fn send(app: &Router, method: Method, origin: &str, headers: &[(&str, &str)]) -> Response {
+        let mut req = Request::builder().method(method).uri("/a.txt").header(header::ORIGIN, origin);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        app.clone().oneshot(req.body(Body::empty()).unwrap()).await.unwrap()
+    }
+
+    fn header(response: &Response, name: HeaderName) -> Option<&str> {
+        response.headers().get(name).map(|value| value.to_str().unwrap())
+    }
+
+    #[tokio::test]
+    async fn preflights_list_what_is_allowed() {
+        let app = app(allowing(&["https://app.example.com"]));
+        let preflight = [("access-control-request-method", "PROPFIND"), ("access-control-request-headers", "depth")];
+        let response = send(&app, Method::OPTIONS, "https://app.example.com", &preflight).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("https://app.example.com"));
+        let methods = header(&response, header::ACCESS_CONTROL_ALLOW_METHODS).unwrap();
+        assert!(methods.contains("PROPFIND") && methods.contains("LOCK"), "{}", methods);
+        let headers = header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap();
+        assert!(headers.contains("depth") && headers.contains("destination"), "{}", headers);
+        assert_eq!(header(&response, header::ACCESS_CONTROL_MAX_AGE), Some("600"));
+    }
+
+    #[tokio::test]
+    async fn only_listed_origins_are_allowed() {
+        let listed = app(allowing(&["https://app.example.com"]));
+
+        let allowed = send(&listed, Method::GET, "https://app.example.com", &[]).await;
+        assert_eq!(header(&allowed, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("https://app.example.com"));
+        assert!(header(&allowed, header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap().contains("etag"));
+        let denied = send(&listed, Method::GET, "https://evil.example.com", &[]).await;
+        assert_eq!(header(&denied, header::ACCESS_CONTROL_ALLOW_ORIGIN), None);
+
+        let any = send(&app(allowing(&["*"])), Method::GET, "https://anywhere.example.com", &[]).await;
+        assert_eq!(header(&any, header::ACCESS_CONTROL_ALLOW_ORIGIN), Some("*"));
+    }
+
+    #[test]
+    fn configs_are_checked() {
+        assert!(layer(&CorsConfig::default()).unwrap().is_none());
+        let credentials = CorsConfig { enabled: true, allow_credentials: true, ..allowing(&["*"]) };
+        assert!(matches!(layer(&credentials), Err(ConfigError::Invalid(_))));
+        let bad_origin = CorsConfig { enabled: true, ..allowing(&["bad\norigin"]) };
+        assert!(matches!(layer(&bad_origin), Err(ConfigError::Invalid(_))));
+    }
+}
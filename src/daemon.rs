@@ -0,0 +1,91 @@
+use daemonize::Daemonize;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+// Detach from the terminal and run in the background. Must be called before
+// any threads are started; the served directory stays the working directory
+pub fn detach() -> Result<(), String> {
+    let cwd = std::env::current_dir().map_err(|err| err.to_string())?;
+    Daemonize::new()
+        .working_directory(cwd)
+        .start()
+        .map_err(|err| err.to_string())
+}
+
+// File holding the server's process ID, locked while the server runs so a
+// second instance cannot take it over, and removed again when dropped
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    // Created before detaching so problems are reported on the terminal.
+    // A file left by a server that is gone is reused; one still locked by a
+    // running server is left alone and this fails
+    pub fn create(path: &Path) -> io::Result<PidFile> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        // The lock belongs to the open file, so it survives detaching.
+        // SAFETY: flock only takes the descriptor, which `file` keeps open
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::WouldBlock {
+                return Err(err);
+            }
+            let mut running = String::new();
+            let _ = file.read_to_string(&mut running);
+            return Err(io::Error::other(match running.trim() {
+                "" => "another instance is running".to_string(),
+                pid => format!("another instance is running as process {}", pid),
+            }));
+        }
+        Ok(PidFile { path: path.to_path_buf(), file })
+    }
+
+    // Record the current process, which changes when detaching
+    pub fn write_pid(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", std::process::id())
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    #[test]
+    fn pid_files_held_by_a_running_server_are_left_alone() {
+        let path = scratch_dir("pid-file").join("server.pid");
+        let mut running = PidFile::create(&path).unwrap();
+        running.write_pid().unwrap();
+
+        let err = PidFile::create(&path).err().unwrap();
+        assert!(err.to_string().contains(&std::process::id().to_string()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+
+        drop(running);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn stale_pid_files_are_reused() {
+        let path = scratch_dir("pid-file-stale").join("server.pid");
+        fs::write(&path, "99999999\n").unwrap();
+
+        let mut pid_file = PidFile::create(&path).unwrap();
+        pid_file.write_pid().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use axum::{
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use sha2::digest::DynDigest;
+use tokio::io::AsyncReadExt;
+
+use crate::{config::DigestConfig, error::AppError, paths::PathResolver, stat_cache::Stat};
+
+pub const WANT_DIGEST: HeaderName = HeaderName::from_static("want-digest");
+pub const DIGEST: HeaderName = HeaderName::from_static("digest");
+
+// Digest algorithms from the RFC 3230 registry that we can compute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    // Name used in Want-Digest and Digest headers
+    pub fn token(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha",
+            Algorithm::Sha256 => "sha-256",
+            Algorithm::Sha512 => "sha-512",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Algorithm> {
+        match token.to_ascii_lowercase().as_str() {
+            "md5" => Some(Algorithm::Md5),
+            "sha" => Some(Algorithm::Sha1),
+            "sha-256" => Some(Algorithm::Sha256),
+            "sha-512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    // Digest header value carrying a digest made with this algorithm
+    pub fn digest_header(self, digest: &[u8]) -> String {
+        format!("{}={}", self.token(), STANDARD.encode(digest))
+    }
+
+    // Name used in ?checksum= queries, after sha256sum and friends
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Md5 => "md5",
+            Algorithm::Sha1 => "sha1",
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    // The algorithm named by a "checksum" query parameter, if any
+    pub fn requested(query: Option<&str>) -> Result<Option<Algorithm>, AppError> {
+        let Some(value) = query.and_then(|query| {
+            query.split('&').find_map(|pair| pair.strip_prefix("checksum="))
+        }) else {
+            return Ok(None);
+        };
+        [Algorithm::Md5, Algorithm::Sha1, Algorithm::Sha256, Algorithm::Sha512]
+            .into_iter()
+            .find(|algorithm| value.eq_ignore_ascii_case(algorithm.name()))
+            .or_else(|| Algorithm::from_token(value))
+            .map(Some)
+            .ok_or_else(|| AppError::Rejected(
+                StatusCode::BAD_REQUEST,
+                "checksum must be md5, sha1, sha256 or sha512".into(),
+            ))
+    }
+
+    pub fn hasher(self) -> Box<dyn DynDigest + Send> {
+        match self {
+            Algorithm::Md5 => Box::new(md5::Md5::default()),
+            Algorithm::Sha1 => Box::new(sha1::Sha1::default()),
+            Algorithm::Sha256 => Box::new(sha2::Sha256::default()),
+            Algorithm::Sha512 => Box::new(sha2::Sha512::default()),
+        }
+    }
+
+    // Pick the supported algorithm the client prefers, per Want-Digest
+    pub fn from_want_digest(headers: &HeaderMap) -> Option<Algorithm> {
+        let want = headers.get(WANT_DIGEST)?.to_str().ok()?;
+
+        let mut best = None;
+        for item in want.split(',') {
+            let mut params = item.split(';');
+            let Some(algorithm) = Algorithm::from_token(params.next().unwrap_or("").trim()) else {
+                continue;
+            };
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((algorithm, quality));
+            }
+        }
+        best.map(|(algorithm, _)| algorithm)
+    }
+}
+
+// A digest of one version of a file, as told apart by its ETag
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DigestKey {
+    path: PathBuf,
+    etag: String,
+    algorithm: Algorithm,
+}
+
+#[derive(Debug)]
+struct CachedDigest {
+    digest: Vec<u8>,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<DigestKey, CachedDigest>,
+    // Incremented on every access, to order entries by recency
+    clock: u64,
+}
+
+// Answer for ?checksum=: the digest in hex, in the format sha256sum -c reads
+pub fn checksum_response(path: &Path, digest: &[u8]) -> Response {
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    (
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        format!("{}  {}\n", hex, name),
+    ).into_response()
+}
+
+// Computes whole-file digests, remembering the most recently used
+#[derive(Debug)]
+pub struct Digests {
+    max_file_size: u64,
+    cache_entries: usize,
+    cache: Mutex<CacheInner>,
+}
+
+impl Digests {
+    pub fn from_config(config: &DigestConfig) -> Digests {
+        Digests {
+            max_file_size: config.max_file_size,
+            cache_entries: config.cache_entries,
+            cache: Mutex::new(CacheInner::default()),
+        }
+    }
+
+    // Digest computed earlier for the version of the file `etag` names,
+    // if it is still remembered
+    pub fn cached(&self, path: &Path, etag: &str, algorithm: Algorithm) -> Option<Vec<u8>> {
+        let key = DigestKey { path: path.to_path_buf(), etag: etag.to_string(), algorithm };
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        let entry = cache.entries.get_mut(&key)?;
+        entry.last_used = clock;
+        Some(entry.digest.clone())
+    }
+
+    // Digest of the file, or None if it is too large to hash on demand.
+    // It is remembered under `etag`; files without one are hashed every
+    // time. The file is opened through `paths`, so its symlink policy
+    // still holds
+    pub async fn compute(
+        &self,
+        paths: &PathResolver,
+        path: &Path,
+        stat: &Stat,
+        etag: Option<&str>,
+        algorithm: Algorithm,
+    ) -> Result<Option<Vec<u8>>, AppError> {
+        if stat.len > self.max_file_size {
+            return Ok(None);
+        }
+        if let Some(digest) = etag.and_then(|etag| self.cached(path, etag, algorithm)) {
+            return Ok(Some(digest));
+        }
+
+        let mut file = paths.open(path).await?;
+        let mut hasher = algorithm.hasher();
+        let mut buffer = vec![0; 65536];
+        loop {
+            let read = file.read(&mut buffer).await
+                .map_err(|err| AppError::from_io(err, path))?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        let digest = hasher.finalize().into_vec();
+
+        if let Some(etag) = etag.filter(|_| self.cache_entries > 0) {
+            let key = DigestKey { path: path.to_path_buf(), etag: etag.to_string(), algorithm };
+            self.insert(key, digest.clone());
+        }
+        Ok(Some(digest))
+    }
+
+    fn insert(&self, key: DigestKey, digest: Vec<u8>) {
+        let mut cache = self.cache.lock().unwrap();
+        while cache.entries.len() >= self.cache_entries {
+            let Some(oldest) = cache.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            cache.entries.remove(&oldest);
+        }
+        cache.clock += 1;
+        let last_used = cache.clock;
+        cache.entries.insert(key, CachedDigest { digest, last_used });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn digests(cache_entries: usize) -> Digests {
+        Digests::from_config(&DigestConfig { cache_entries, ..DigestConfig::default() })
+    }
+
+    // Hash `path` as the version `etag` names
+    async fn sha256(digests: &Digests, path: &Path, etag: Option<&str>) -> Option<String> {
+        let paths = PathResolver::from_config(&Default::default()).unwrap();
+        let stat = Stat::from(&std::fs::metadata(path).unwrap());
+        let digest = digests.compute(&paths, path, &stat, etag, Algorithm::Sha256).await.unwrap()?;
+        Some(STANDARD.encode(digest))
+    }
+
+    #[tokio::test]
+    async fn digests_are_remembered_by_etag() {
+        let path = scratch_dir("digest-cache").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let digests = digests(16);
+        let hello = sha256(&digests, &path, Some("\"v1\"")).await.unwrap();
+
+        // The cache answers for the version it was computed for
+        std::fs::write(&path, "other").unwrap();
+        assert_eq!(sha256(&digests, &path, Some("\"v1\"")).await.unwrap(), hello);
+        assert_ne!(sha256(&digests, &path, Some("\"v2\"")).await.unwrap(), hello);
+        assert_ne!(sha256(&digests, &path, None).await.unwrap(), hello);
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_digest_goes_first() {
+        let path = scratch_dir("digest-lru").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let digests = digests(2);
+        for etag in ["a", "b"] {
+            sha256(&digests, &path, Some(etag)).await;
+        }
+        assert!(digests.cached(&path, "a", Algorithm::Sha256).is_some());
+
+        sha256(&digests, &path, Some("c")).await;
+        assert!(digests.cached(&path, "a", Algorithm::Sha256).is_some());
+        assert!(digests.cached(&path, "b", Algorithm::Sha256).is_none());
+        assert!(digests.cached(&path, "c", Algorithm::Sha256).is_some());
+    }
+
+    #[tokio::test]
+    async fn large_files_are_not_hashed() {
+        let path = scratch_dir("digest-large").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let digests = Digests::from_config(&DigestConfig { max_file_size: 4, ..DigestConfig::default() });
+        assert_eq!(sha256(&digests, &path, None).await, None);
+    }
+
+    #[test]
+    fn want_digest_picks_the_preferred_algorithm() {
+        let mut headers = HeaderMap::new();
+        headers.insert(WANT_DIGEST, "sha-256;q=0.5, SHA-512;q=0.9, crc32".parse().unwrap());
+        assert_eq!(Algorithm::from_want_digest(&headers), Some(Algorithm::Sha512));
+        headers.insert(WANT_DIGEST, "sha;q=0, crc32".parse().unwrap());
+        assert_eq!(Algorithm::from_want_digest(&headers), None);
+    }
+}
@@ -0,0 +1,494 @@
+use axum::{
+    body::{boxed, Full},
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{fs, future::{ready, Ready}, io, path::Path, sync::Arc};
+
+use crate::{
+    config::{ConfigError, ErrorConfig, ErrorFormat},
+    i18n::{self, Catalog, Catalogs},
+    request_id::RequestId,
+    security_headers::GeneratedHtml,
+    state::AppState,
+};
+
+// Custom error type for our application
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    PermissionDenied(String),
+    InvalidPath(String),
+    Conflict(String),
+    Locked(String),
+    // A conditional request header that does not hold, saying which
+    PreconditionFailed(String),
+    PayloadTooLarge(String),
+    InsufficientStorage(String),
+    // Protocol-level rejection carrying its own status and message
+    Rejected(StatusCode, String),
+    // Method the resource does not support, with the Allow header value
+    // listing those it does
+    MethodNotAllowed(Method, &'static str),
+    // Unexpected IO failure; details are logged, never sent to the client
+    Io(String, io::Error),
+}
+
+impl AppError {
+    // Map an IO error on `path` to the variant matching its kind
+    pub fn from_io(err: io::Error, path: &Path) -> AppError {
+        let path = path.display().to_string();
+
+        match err.kind() {
+            io::ErrorKind::NotFound => AppError::NotFound(path),
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem =>
+                AppError::PermissionDenied(path),
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidFilename =>
+                AppError::InvalidPath(path),
+            io::ErrorKind::AlreadyExists
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::DirectoryNotEmpty
+            // EBUSY: a mount point or a file another process holds open
+            | io::ErrorKind::ResourceBusy => AppError::Conflict(path),
+            io::ErrorKind::FileTooLarge => AppError::PayloadTooLarge(path),
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded =>
+                AppError::InsufficientStorage(path),
+            _ => AppError::Io(path, err),
+        }
+    }
+}
+
+// Status and message of an error response, kept in the response extensions
+// so the rendering middleware can re-encode it for the client
+#[derive(Debug, Clone)]
+pub struct ErrorReport {
+    pub status: StatusCode,
+    pub message: String,
+    // Catalog key of the message and its placeholder values, for pages in
+    // the client's language
+    pub key: Option<&'static str>,
+    pub args: Vec<(&'static str, String)>,
+}
+
+// Implement error responses
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let allow = match &self {
+            AppError::MethodNotAllowed(_, allow) => Some(*allow),
+            _ => None,
+        };
+        let (status, message, key, args) = match self {
+            AppError::NotFound(path) => (
+                StatusCode::NOT_FOUND, format!("File not found: {}", path),
+                Some("not-found"), vec![("path", path)],
+            ),
+            AppError::PermissionDenied(path) => (
+                StatusCode::FORBIDDEN, format!("Permission denied: {}", path),
+                Some("permission-denied"), vec![("path", path)],
+            ),
+            AppError::InvalidPath(path) => (
+                StatusCode::BAD_REQUEST, format!("Invalid path: {}", path),
+                Some("invalid-path"), vec![("path", path)],
+            ),
+            AppError::Conflict(path) => (
+                StatusCode::CONFLICT, format!("Conflicting resource: {}", path),
+                Some("conflict"), vec![("path", path)],
+            ),
+            AppError::Locked(path) => (
+                StatusCode::LOCKED, format!("Resource is locked: {}", path),
+                Some("locked"), vec![("path", path)],
+            ),
+            AppError::PreconditionFailed(reason) => (
+                StatusCode::PRECONDITION_FAILED, format!("Precondition failed: {}", reason),
+                Some("precondition-failed"), vec![("reason", reason)],
+            ),
+            AppError::PayloadTooLarge(path) => (
+                StatusCode::PAYLOAD_TOO_LARGE, format!("File too large: {}", path),
+                Some("payload-too-large"), vec![("path", path)],
+            ),
+            AppError::InsufficientStorage(path) => (
+                StatusCode::INSUFFICIENT_STORAGE, format!("Insufficient storage for {}", path),
+                Some("insufficient-storage"), vec![("path", path)],
+            ),
+            // Free-form messages stay in English
+            AppError::Rejected(status, message) => (status, message, None, Vec::new()),
+            AppError::MethodNotAllowed(method, allow) => (
+                StatusCode::METHOD_NOT_ALLOWED,
+                format!("{} is not supported here; allowed methods: {}", method, allow),
+                Some("method-not-allowed"),
+                vec![("method", method.to_string()), ("allow", allow.to_string())],
+            ),
+            AppError::Io(path, err) => {
+                tracing::error!("IO error on {}: {}", path, err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string(),
+                    Some("internal-error"), Vec::new(),
+                )
+            }
+        };
+
+        let mut response = (status, message.clone()).into_response();
+        if let Some(allow) = allow {
+            response.headers_mut().insert(header::ALLOW, HeaderValue::from_static(allow));
+        }
+        response.extensions_mut().insert(ErrorReport { status, message, key, args });
+        response
+    }
+}
+
+// Whether an error, or any error behind it, is a client going away in the
+// middle of a request or response rather than a fault on either side
+pub fn is_disconnect(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(err) = err.downcast_ref::<hyper::Error>() {
+            if err.is_incomplete_message() || err.is_closed() || err.is_canceled() {
+                return true;
+            }
+        }
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            if matches!(
+                err.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::UnexpectedEof
+            ) {
+                return true;
+            }
+        }
+        current = err.source();
+    }
+    false
+}
+
+// Fallback for the methods a route does not handle, answering 405 with
+// the `allow` methods it does
+pub fn method_not_allowed(allow: &'static str) -> impl Fn(Method) -> Ready<AppError> + Clone {
+    move |method| ready(AppError::MethodNotAllowed(method, allow))
+}
+
+const DEFAULT_HTML_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"{{lang}}\">
+<head><title>{{status}} {{reason}}</title></head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>{{message}}</p>
+<p><small>{{request_id_label}}: {{request_id}}</small></p>
+</body>
+</html>
+";
+
+// Renders error reports in the format configured or negotiated per request
+#[derive(Debug, Clone)]
+pub struct ErrorPages {
+    format: ErrorFormat,
+    html_template: Arc<str>,
+    catalogs: Arc<Catalogs>,
+}
+
+impl ErrorPages {
+    pub fn from_config(config: &ErrorConfig) -> Result<ErrorPages, ConfigError> {
+        let html_template = match &config.html_template {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|err| ConfigError::Io(path.clone(), err))?,
+            None => DEFAULT_HTML_TEMPLATE.to_string(),
+        };
+
+        let catalogs = match &config.catalog_dir {
+            Some(dir) => Catalogs::load(dir)?,
+            None => Catalogs::default(),
+        };
+
+        Ok(ErrorPages {
+            format: config.format,
+            html_template: html_template.into(),
+            catalogs: Arc::new(catalogs),
+        })
+    }
+
+    fn format_for(&self, headers: &HeaderMap) -> ErrorFormat {
+        match self.format {
+            ErrorFormat::Negotiate => negotiate(headers),
+            format => format,
+        }
+    }
+
+    // HTML pages are written in the language negotiated from
+    // Accept-Language, where a catalog has the strings; the other formats
+    // are for programs and stay in English
+    fn render(
+        &self,
+        format: ErrorFormat,
+        report: &ErrorReport,
+        request_id: &str,
+        language: Option<(&str, &Catalog)>,
+    ) -> (&'static str, String) {
+        let reason = report.status.canonical_reason().unwrap_or("Error");
+
+        match format {
+            ErrorFormat::Html => {
+                let catalog = language.map(|(_, catalog)| catalog);
+                let reason = catalog.and_then(|catalog| catalog.reason(report.status)).unwrap_or(reason);
+                let message = report.key
+                    .and_then(|key| catalog?.message(key, &report.args))
+                    .unwrap_or_else(|| report.message.clone());
+                let label = catalog.and_then(Catalog::request_id).unwrap_or("Request ID");
+                let body = i18n::substitute(&self.html_template, "{{", "}}", |name| Some(match name {
+                    "lang" => escape_markup(language.map_or("en", |(tag, _)| tag)),
+                    "status" => report.status.as_str().to_string(),
+                    "reason" => escape_markup(reason),
+                    "message" => escape_markup(&message),
+                    "request_id_label" => escape_markup(label),
+                    "request_id" => escape_markup(request_id),
+                    _ => return None,
+                }));
+                ("text/html; charset=utf-8", body)
+            }
+            ErrorFormat::Xml => {
+                let body = format!(
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                     <D:error xmlns:D=\"DAV:\">\
+                     <D:responsedescription>{}</D:responsedescription>\
+                     </D:error>\n",
+                    escape_markup(&format!("{} (request ID {})", report.message, request_id)),
+                );
+                ("application/xml; charset=utf-8", body)
+            }
+            ErrorFormat::Json => {
+                let body = serde_json::json!({
+                    "status": report.status.as_u16(),
+                    "error": reason,
+                    "message": report.message,
+                    "request_id": request_id,
+                });
+                ("application/json", body.to_string())
+            }
+            ErrorFormat::Text | ErrorFormat::Negotiate => (
+                "text/plain; charset=utf-8",
+                format!("{} (request ID {})", report.message, request_id),
+            ),
+        }
+    }
+}
+
+// Middleware re-encoding error responses according to the client's Accept header
+pub async fn render_errors<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let pages = state.error_pages.get();
+    let format = pages.format_for(req.headers());
+    let language = match format {
+        ErrorFormat::Html => pages.catalogs.negotiate(req.headers()),
+        _ => None,
+    };
+    let request_id = req.extensions().get::<RequestId>()
+        .map_or_else(|| "-".to_string(), |id| id.0.clone());
+    let response = next.run(req).await;
+
+    let Some(report) = response.extensions().get::<ErrorReport>().cloned() else {
+        return response;
+    };
+
+    let (content_type, body) = pages.render(format, &report, &request_id, language);
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Some(language) = language.and_then(|(tag, _)| HeaderValue::from_str(tag).ok()) {
+        parts.headers.insert(header::CONTENT_LANGUAGE, language);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    if format == ErrorFormat::Html {
+        parts.extensions.insert(GeneratedHtml);
+    }
+
+    Response::from_parts(parts, boxed(Full::from(body)))
+}
+
+// Pick the supported format with the highest quality value, defaulting to text
+fn negotiate(headers: &HeaderMap) -> ErrorFormat {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+        return ErrorFormat::Text;
+    };
+
+    let mut best = (ErrorFormat::Text, 0.0);
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let media = params.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let format = match media.as_str() {
+            "text/html" | "application/xhtml+xml" => ErrorFormat::Html,
+            "application/xml" | "text/xml" => ErrorFormat::Xml,
+            "application/json" => ErrorFormat::Json,
+            "text/plain" => ErrorFormat::Text,
+            _ => continue,
+        };
+
+        if quality > best.1 {
+            best = (format, quality);
+        }
+    }
+
+    best.0
+}
+
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn io_status(kind: io::ErrorKind) -> StatusCode {
+        AppError::from_io(io::Error::from(kind), Path::new("file")).into_response().status()
+    }
+
+    #[test]
+    fn io_errors_map_to_statuses() {
+        assert_eq!(io_status(io::ErrorKind::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(io_status(io::ErrorKind::PermissionDenied), StatusCode::FORBIDDEN);
+        assert_eq!(io_status(io::ErrorKind::AlreadyExists), StatusCode::CONFLICT);
+        assert_eq!(io_status(io::ErrorKind::ResourceBusy), StatusCode::CONFLICT);
+        assert_eq!(io_status(io::ErrorKind::StorageFull), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(io_status(io::ErrorKind::FileTooLarge), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(io_status(io::ErrorKind::TimedOut), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn io_error_details_are_not_sent() {
+        let err = io::Error::other("disk controller on fire");
+        let report = AppError::from_io(err, Path::new("file")).into_response()
+            .extensions().get::<ErrorReport>().cloned().unwrap();
+        assert_eq!(report.message, "Internal server error");
+    }
+
+    fn report(message: &str) -> ErrorReport {
+        ErrorReport {
+            status: StatusCode::NOT_FOUND,
+            message: message.into(),
+            key: None,
+            args: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn html_pages_fill_in_placeholders_once() {
+        let pages = ErrorPages::from_config(&ErrorConfig::default()).unwrap();
+        let (content_type, body) = pages.render(
+            ErrorFormat::Html, &report("File not found: {{request_id}}<b>"), "abc123", None,
+        );
+        assert_eq!(content_type, "text/html; charset=utf-8");
+        assert!(body.contains("<p>File not found: {{request_id}}&lt;b&gt;</p>"), "{}", body);
+        assert!(body.contains("Request ID: abc123"));
+        assert!(body.contains("<title>404 Not Found</title>"));
+    }
+
+    #[test]
+    fn failed_preconditions_are_412() {
+        let response = AppError::PreconditionFailed("If-Match does not match".into()).into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let report = response.extensions().get::<ErrorReport>().unwrap();
+        assert_eq!(report.key, Some("precondition-failed"));
+        assert_eq!(report.args, [("reason", "If-Match does not match".to_string())]);
+    }
+
+    fn accepting(accept: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_str(accept).unwrap());
+        headers
+    }
+
+    #[test]
+    fn formats_are_negotiated_by_quality() {
+        assert_eq!(negotiate(&HeaderMap::new()), ErrorFormat::Text);
+        assert_eq!(negotiate(&accepting("*/*")), ErrorFormat::Text);
+        assert_eq!(negotiate(&accepting("text/html,application/xhtml+xml")), ErrorFormat::Html);
+        assert_eq!(negotiate(&accepting("application/json;q=0.5, text/xml;q=0.9")), ErrorFormat::Xml);
+        assert_eq!(negotiate(&accepting("image/png, Application/JSON")), ErrorFormat::Json);
+        // The first of equally preferred formats wins
+        assert_eq!(negotiate(&accepting("application/json, text/html")), ErrorFormat::Json);
+    }
+
+    #[test]
+    fn configured_formats_ignore_accept() {
+        let config = ErrorConfig { format: ErrorFormat::Json, ..ErrorConfig::default() };
+        let pages = ErrorPages::from_config(&config).unwrap();
+        assert_eq!(pages.format_for(&accepting("text/html")), ErrorFormat::Json);
+    }
+
+    #[test]
+    fn machine_formats_carry_the_request_id() {
+        let pages = ErrorPages::from_config(&ErrorConfig::default()).unwrap();
+        let report = report("File not found: a&b.txt");
+
+        let (content_type, body) = pages.render(ErrorFormat::Json, &report, "abc123", None);
+        assert_eq!(content_type, "application/json");
+        let json: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(json["status"], 404);
+        assert_eq!(json["error"], "Not Found");
+        assert_eq!(json["message"], "File not found: a&b.txt");
+        assert_eq!(json["request_id"], "abc123");
+
+        let (_, body) = pages.render(ErrorFormat::Xml, &report, "abc123", None);
+        let description = "File not found: a&amp;b.txt (request ID abc123)";
+        assert!(body.contains(&format!("<D:responsedescription>{}</D:responsedescription>", description)));
+
+        let (content_type, body) = pages.render(ErrorFormat::Text, &report, "abc123", None);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(body, "File not found: a&b.txt (request ID abc123)");
+    }
+
+    #[test]
+    fn html_pages_use_the_configured_template_and_catalog() {
+        let dir = scratch_dir("error-pages");
+        let template = "{{lang}}|{{status}}|{{reason}}|{{message}}|{{request_id_label}}";
+        std::fs::write(dir.join("page.html"), template).unwrap();
+        std::fs::create_dir_all(dir.join("catalogs")).unwrap();
+        std::fs::write(
+            dir.join("catalogs/de.toml"),
+            concat!(
+                "request_id = \"Anfrage\"\n",
+                "[reasons]\n404 = \"Nicht gefunden\"\n",
+                "[messages]\nnot-found = \"{path} fehlt\"\n",
+            ),
+        ).unwrap();
+        let pages = ErrorPages::from_config(&ErrorConfig {
+            html_template: Some(dir.join("page.html")),
+            catalog_dir: Some(dir.join("catalogs")),
+            ..ErrorConfig::default()
+        }).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("fr;q=0.9, de;q=0.8"));
+        let language = pages.catalogs.negotiate(&headers);
+        let report = AppError::NotFound("a.txt".into()).into_response()
+            .extensions().get::<ErrorReport>().cloned().unwrap();
+        let (_, body) = pages.render(ErrorFormat::Html, &report, "abc123", language);
+        assert_eq!(body, "de|404|Nicht gefunden|a.txt fehlt|Anfrage");
+
+        // Without a matching catalog the page stays in English
+        let (_, body) = pages.render(ErrorFormat::Html, &report, "abc123", None);
+        assert!(body.starts_with("en|404|Not Found|"), "{}", body);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{EtagConfig, EtagMode},
+    digest::{Algorithm, Digests},
+    error::AppError,
+    paths::PathResolver,
+    stat_cache::Stat,
+};
+
+// Extended attribute remembering a file's content hash across restarts
+#[cfg(unix)]
+const HASH_ATTRIBUTE: &str = "user.axum-webdav.sha256";
+
+// Makes the ETags sent with files
+#[derive(Debug)]
+pub struct Etags {
+    mode: EtagMode,
+    store_hashes: bool,
+}
+
+impl Etags {
+    pub fn from_config(config: &EtagConfig) -> Etags {
+        Etags { mode: config.mode, store_hashes: config.store_hashes }
+    }
+
+    // Whether making a tag can mean hashing the whole file
+    pub fn hashes_content(&self) -> bool {
+        self.mode == EtagMode::Content
+    }
+
+    // The entity tag of a file, or None when ETags are off. Only hashes
+    // computed by this process make strong tags: an mtime can stay the same
+    // across a rewrite, so fast tags are weak, as are content tags taken
+    // from an extended attribute anyone able to write the file could have
+    // set, and those falling back to fast tags for files too large to hash
+    // on demand
+    pub async fn tag(
+        &self,
+        digests: &Digests,
+        paths: &PathResolver,
+        path: &Path,
+        stat: &Stat,
+    ) -> Result<Option<String>, AppError> {
+        match self.mode {
+            EtagMode::Off => Ok(None),
+            EtagMode::Fast => Ok(Some(fast_tag(stat))),
+            EtagMode::Content => Ok(Some(match self.content_hash(digests, paths, path, stat).await? {
+                Some(Hash::Computed(hash)) => format!("\"{}\"", hash),
+                Some(Hash::Stored(hash)) => format!("W/\"{}\"", hash),
+                None => fast_tag(stat),
+            })),
+        }
+    }
+
+    // SHA-256 of the file: remembered or computed, or when hashes are
+    // stored, from its extended attribute if that was written for the same
+    // version of the file. Computed hashes are then stored for next time
+    async fn content_hash(
+        &self,
+        digests: &Digests,
+        paths: &PathResolver,
+        path: &Path,
+        stat: &Stat,
+    ) -> Result<Option<Hash>, AppError> {
+        // Content tags cannot key the hash they come from; fast tags tell
+        // versions apart well enough for that
+        let version = version(stat);
+        let key = fast_tag(stat);
+        if let Some(digest) = digests.cached(path, &key, Algorithm::Sha256) {
+            return Ok(Some(Hash::Computed(hex(&digest))));
+        }
+        if self.store_hashes {
+            if let Some(hash) = stored_hash(path, &version).await {
+                return Ok(Some(Hash::Stored(hash)));
+            }
+        }
+
+        let Some(digest) = digests.compute(paths, path, stat, Some(&key), Algorithm::Sha256).await? else {
+            return Ok(None);
+        };
+        let hash = hex(&digest);
+        if self.store_hashes {
+            store_hash(path, &format!("{} {}", version, hash)).await;
+        }
+        Ok(Some(Hash::Computed(hash)))
+    }
+}
+
+// Where a content hash came from
+enum Hash {
+    Computed(String),
+    Stored(String),
+}
+
+fn fast_tag(stat: &Stat) -> String {
+    format!("W/\"{}\"", version(stat))
+}
+
+// Identifies a version of a file by its device, inode, modification time
+// and size
+fn version(stat: &Stat) -> String {
+    let modified = stat.modified.map_or(0, nanos);
+    match stat.file_id {
+        Some((device, inode)) => format!("{:x}-{:x}-{:x}-{:x}", device, inode, modified, stat.len),
+        None => format!("{:x}-{:x}", modified, stat.len),
+    }
+}
+
+fn nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(unix)]
+async fn stored_hash(path: &Path, version: &str) -> Option<String> {
+    let path = path.to_path_buf();
+    let value = tokio::task::spawn_blocking(move || xattr::get(&path, HASH_ATTRIBUTE))
+        .await.ok()?.ok()??;
+    let value = String::from_utf8(value).ok()?;
+    let (stored, hash) = value.rsplit_once(' ')?;
+    (stored == version).then(|| hash.to_string())
+}
+
+// Filesystems without extended attributes just hash again next time
+#[cfg(unix)]
+async fn store_hash(path: &Path, value: &str) {
+    let (path, value) = (path.to_path_buf(), value.to_string());
+    let _ = tokio::task::spawn_blocking(move || xattr::set(&path, HASH_ATTRIBUTE, value.as_bytes())).await;
+}
+
+#[cfg(not(unix))]
+async fn stored_hash(_path: &Path, _version: &str) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+async fn store_hash(_path: &Path, _value: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, DigestConfig},
+        state::AppState,
+        testing::scratch_dir,
+    };
+
+    // SHA-256 of "hello"
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn state(mode: EtagMode, store_hashes: bool) -> AppState {
+        AppState::from_config(&Config {
+            etag: EtagConfig { mode, store_hashes },
+            ..Config::default()
+        }).unwrap()
+    }
+
+    async fn tag(state: &AppState, path: &Path) -> Option<String> {
+        let stat = Stat::from(&std::fs::metadata(path).unwrap());
+        state.etags.tag(&state.digests, &state.paths.get(), path, &stat).await.unwrap()
+    }
+
+    // A file reading "hello", or None where extended attributes cannot be
+    // set, so the tests relying on them are skipped
+    fn hello_with_attributes(name: &str) -> Option<std::path::PathBuf> {
+        let path = scratch_dir(name).join("hello.txt");
+        std::fs::write(&path, "hello").unwrap();
+        xattr::set(&path, "user.axum-webdav.test", b"").ok()?;
+        Some(path)
+    }
+
+    #[tokio::test]
+    async fn fast_tags_are_weak_and_follow_the_file() {
+        let path = scratch_dir("etag-fast").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let state = state(EtagMode::Fast, false);
+
+        let first = tag(&state, &path).await.unwrap();
+        assert!(first.starts_with("W/\""), "{}", first);
+        assert_eq!(tag(&state, &path).await.unwrap(), first);
+        std::fs::write(&path, "hello, world").unwrap();
+        assert_ne!(tag(&state, &path).await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn content_tags_are_strong_hashes() {
+        let path = scratch_dir("etag-content").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        assert_eq!(tag(&state(EtagMode::Content, false), &path).await, Some(format!("\"{}\"", HELLO)));
+        assert_eq!(tag(&state(EtagMode::Off, false), &path).await, None);
+    }
+
+    #[tokio::test]
+    async fn files_too_large_to_hash_get_fast_tags() {
+        let path = scratch_dir("etag-large").join("file.txt");
+        std::fs::write(&path, "hello").unwrap();
+        let state = AppState::from_config(&Config {
+            etag: EtagConfig { mode: EtagMode::Content, store_hashes: false },
+            digest: DigestConfig { max_file_size: 4, ..DigestConfig::default() },
+            ..Config::default()
+        }).unwrap();
+
+        assert!(tag(&state, &path).await.unwrap().starts_with("W/\""));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stored_hashes_make_weak_tags() {
+        let Some(path) = hello_with_attributes("etag-stored") else {
+            return;
+        };
+        assert_eq!(tag(&state(EtagMode::Content, true), &path).await, Some(format!("\"{}\"", HELLO)));
+
+        // Another process finds the hash but cannot vouch for it
+        assert_eq!(tag(&state(EtagMode::Content, true), &path).await, Some(format!("W/\"{}\"", HELLO)));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn attributes_are_ignored_unless_hashes_are_stored() {
+        let Some(path) = hello_with_attributes("etag-unstored") else {
+            return;
+        };
+        let stat = Stat::from(&std::fs::metadata(&path).unwrap());
+        let planted = format!("{} {}", version(&stat), "0".repeat(64));
+        xattr::set(&path, HASH_ATTRIBUTE, planted.as_bytes()).unwrap();
+
+        assert_eq!(tag(&state(EtagMode::Content, false), &path).await, Some(format!("\"{}\"", HELLO)));
+        assert_eq!(xattr::get(&path, HASH_ATTRIBUTE).unwrap().unwrap(), planted.as_bytes());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stale_hashes_are_recomputed() {
+        let Some(path) = hello_with_attributes("etag-stale") else {
+            return;
+        };
+        let stale = format!("{} {}", "0-0-0-5", "0".repeat(64));
+        xattr::set(&path, HASH_ATTRIBUTE, stale.as_bytes()).unwrap();
+
+        assert_eq!(tag(&state(EtagMode::Content, true), &path).await, Some(format!("\"{}\"", HELLO)));
+        let stored = xattr::get(&path, HASH_ATTRIBUTE).unwrap().unwrap();
+        assert!(String::from_utf8(stored).unwrap().ends_with(HELLO));
+    }
+}
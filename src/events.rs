@@ -0,0 +1,188 @@
+use std::{convert::Infallible, path::Path, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use futures_util::{stream, Stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    config::EventConfig,
+    error::{self, AppError},
+    paths,
+    state::AppState,
+};
+
+// What happened to a path
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+}
+
+impl ChangeKind {
+    fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Create => "create",
+            ChangeKind::Modify => "modify",
+            ChangeKind::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    // Relative to the served directory, '/'-separated
+    pub path: String,
+}
+
+// Bus carrying changes to the served tree to everyone subscribed
+#[derive(Debug)]
+pub struct Events {
+    sender: broadcast::Sender<Change>,
+    keep_alive: Duration,
+}
+
+impl Events {
+    pub fn from_config(config: &EventConfig) -> Events {
+        Events {
+            sender: broadcast::channel(config.buffer.max(1)).0,
+            keep_alive: Duration::from_secs(config.keep_alive_secs.max(1)),
+        }
+    }
+
+    pub fn publish(&self, kind: ChangeKind, path: &Path) {
+        // Fails only when nobody is listening
+        let _ = self.sender.send(Change { kind, path: paths::glob_path(path) });
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/.events", get(subscribe)
+        .fallback(error::method_not_allowed("GET")))
+}
+
+#[derive(Debug, Deserialize)]
+struct Subscription {
+    // Subtree to watch; the whole served directory when empty
+    #[serde(default)]
+    path: String,
+}
+
+// Stream changes under the subscribed subtree as server-sent events named
+// after the change kind, carrying the change as JSON. A subscriber falling
+// too far behind gets a "lagged" event and should rescan
+async fn subscribe(
+    State(state): State<Arc<AppState>>,
+    Query(subscription): Query<Subscription>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let events = state.events.as_ref().ok_or_else(|| AppError::NotFound(".events".into()))?;
+    let prefix = match subscription.path.trim_matches('/') {
+        "" => String::new(),
+        path => {
+            let dir = state.paths.get().resolve(path).await?;
+            if !state.stats.stat(&dir).await?.is_dir {
+                return Err(AppError::InvalidPath(format!("{} is not a directory", dir.display())));
+            }
+            paths::glob_path(&dir)
+        }
+    };
+
+    let receiver = events.sender.subscribe();
+    let stream = stream::unfold((receiver, state.clone()), move |(mut receiver, state)| {
+        let prefix = prefix.clone();
+        async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(change) => {
+                        if !is_within(&change.path, &prefix)
+                            || !state.paths.get().is_visible(Path::new(&change.path))
+                        {
+                            continue;
+                        }
+                        Event::default()
+                            .event(change.kind.name())
+                            .json_data(&change)
+                            .expect("changes serialize")
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        Event::default().event("lagged").data(missed.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), (receiver, state)));
+            }
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(events.keep_alive)))
+}
+
+fn is_within(path: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, PathConfig},
+        testing::scratch_dir,
+    };
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use hyper::body::HttpBody;
+    use tower::ServiceExt;
+
+    #[test]
+    fn subtrees_contain_their_own_paths_only() {
+        assert!(is_within("docs/a.txt", ""));
+        assert!(is_within("docs", "docs"));
+        assert!(is_within("docs/a.txt", "docs"));
+        assert!(!is_within("docs2/a.txt", "docs"));
+        assert!(!is_within("other/docs/a.txt", "docs"));
+    }
+
+    #[tokio::test]
+    async fn subscribers_hear_about_visible_changes_in_their_subtree() {
+        let dir = scratch_dir("events_subtree");
+        let state = Arc::new(AppState::from_config(&Config {
+            events: EventConfig { enabled: true, ..EventConfig::default() },
+            paths: PathConfig { hide_hidden: true, ..PathConfig::default() },
+            ..Config::default()
+        }).unwrap());
+        let app = routes().with_state(state.clone());
+        let uri = format!("/.events?path={}", paths::glob_path(&dir));
+        let response = app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let events = state.events.as_ref().unwrap();
+        events.publish(ChangeKind::Create, Path::new("elsewhere/a.txt"));
+        events.publish(ChangeKind::Create, &dir.join(".hidden"));
+        events.publish(ChangeKind::Delete, &dir.join("a.txt"));
+        let mut body = response.into_body();
+        let event = String::from_utf8(body.data().await.unwrap().unwrap().to_vec()).unwrap();
+        let path = paths::glob_path(&dir.join("a.txt"));
+        assert_eq!(event, format!("event:delete\ndata:{{\"kind\":\"delete\",\"path\":\"{}\"}}\n\n", path));
+    }
+
+    #[tokio::test]
+    async fn subscriptions_need_a_directory() {
+        let dir = scratch_dir("events_file");
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        let state = Arc::new(AppState::from_config(&Config {
+            events: EventConfig { enabled: true, ..EventConfig::default() },
+            ..Config::default()
+        }).unwrap());
+        let uri = format!("/.events?path={}", paths::glob_path(&dir.join("a.txt")));
+        let response = routes().with_state(state).oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
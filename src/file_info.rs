@@ -0,0 +1,110 @@
+use std::{fs::Metadata, path::Path};
+
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tokio::fs;
+
+use crate::{access_log, error::AppError, paths, state::AppState};
+
+// What GET /path?stat=json reports about a file or directory
+#[derive(Debug, Serialize)]
+struct FileInfo {
+    path: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    // RFC 3339 times in UTC, left out where the platform has none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<String>,
+    // Last status change (ctime), Unix only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    // Permission bits in octal, Unix only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    readonly: bool,
+    // The file's ETag, in the configured mode; left out when ETags are off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    etag: Option<String>,
+}
+
+// Whether the query asks for metadata instead of content
+pub fn requested(query: Option<&str>) -> bool {
+    query.is_some_and(|query| query.split('&').any(|pair| pair == "stat=json"))
+}
+
+// Metadata of `path`, with the ETag a GET of it would carry
+pub async fn response(state: &AppState, path: &Path, etag: Option<&str>) -> Result<Response, AppError> {
+    let metadata = fs::metadata(path).await.map_err(|err| AppError::from_io(err, path))?;
+    let mime = metadata.is_file().then(|| state.mime_types.get().content_type(path));
+    let info = FileInfo {
+        path: paths::glob_path(path),
+        kind: if metadata.is_dir() { "directory" } else { "file" },
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        modified: metadata.modified().ok().map(access_log::rfc3339_time),
+        created: metadata.created().ok().map(access_log::rfc3339_time),
+        changed: changed(&metadata),
+        mime,
+        mode: mode(&metadata),
+        readonly: metadata.permissions().readonly(),
+        etag: etag.map(str::to_string),
+    };
+    Ok(Json(info).into_response())
+}
+
+#[cfg(unix)]
+fn changed(metadata: &Metadata) -> Option<String> {
+    use std::{os::unix::fs::MetadataExt, time::{Duration, UNIX_EPOCH}};
+
+    let secs = u64::try_from(metadata.ctime()).ok()?;
+    let nanos = u32::try_from(metadata.ctime_nsec()).ok()?;
+    Some(access_log::rfc3339_time(UNIX_EPOCH + Duration::new(secs, nanos)))
+}
+
+#[cfg(not(unix))]
+fn changed(_metadata: &Metadata) -> Option<String> {
+    None
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Some(format!("{:04o}", metadata.permissions().mode() & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &Metadata) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, testing::scratch_dir};
+
+    async fn info(path: &Path, etag: Option<&str>) -> serde_json::Value {
+        let state = AppState::from_config(&Config::default()).unwrap();
+        let body = response(&state, path, etag).await.unwrap().into_body();
+        serde_json::from_slice(&hyper::body::to_bytes(body).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn files_report_their_etag() {
+        let path = scratch_dir("file-info").join("a.txt");
+        std::fs::write(&path, "text").unwrap();
+
+        let tagged = info(&path, Some("\"abc\"")).await;
+        assert_eq!(tagged["etag"], "\"abc\"");
+        assert_eq!(tagged["size"], 4);
+        assert_eq!(tagged["type"], "file");
+        assert!(info(&path, None).await.get("etag").is_none());
+    }
+}
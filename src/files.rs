@@ -0,0 +1,260 @@
+use axum::body::{boxed, BoxBody, Bytes, Full, StreamBody};
+use memmap2::Mmap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+use tokio::{fs, io::{AsyncReadExt, BufReader}};
+use tokio_util::io::ReaderStream;
+
+use crate::{config::FileConfig, error::AppError, stat_cache::Stat, timing::{self, TimedReader}};
+
+// Turns opened files into response bodies
+#[derive(Debug)]
+pub struct FileBodies {
+    mmap_max_size: u64,
+    read_buffer_size: usize,
+    chunk_size: usize,
+    cache: Option<FileCache>,
+}
+
+impl FileBodies {
+    pub fn from_config(config: &FileConfig) -> FileBodies {
+        let cache = (config.cache_max_bytes > 0 && config.cache_entries > 0).then(|| FileCache {
+            max_bytes: config.cache_max_bytes,
+            max_entries: config.cache_entries,
+            max_file_size: config.cache_max_file_size,
+            inner: Mutex::new(CacheInner::default()),
+        });
+
+        FileBodies {
+            mmap_max_size: config.mmap_max_size,
+            read_buffer_size: config.read_buffer_size,
+            chunk_size: config.chunk_size,
+            cache,
+        }
+    }
+
+    // Body with the contents of the file at `path` if they are cached for
+    // the version `stat` describes, which spares opening it
+    pub fn cached(&self, path: &Path, stat: &Stat) -> Option<BoxBody> {
+        let cache = self.cache.as_ref().filter(|cache| stat.len <= cache.max_file_size)?;
+        let data = cache.get(path, stat.len, stat.modified)?;
+        Some(boxed(Full::from(data)))
+    }
+
+    // Body with the contents of `file`, opened from `path` and described by
+    // `stat`. It never carries more than `stat.len` bytes, so it matches
+    // the Content-Length sent with it if the file grew meanwhile
+    pub async fn body(
+        &self,
+        path: &Path,
+        mut file: fs::File,
+        stat: &Stat,
+    ) -> Result<BoxBody, AppError> {
+        let len = stat.len;
+
+        if let Some(cache) = self.cache.as_ref().filter(|cache| len <= cache.max_file_size) {
+            let modified = stat.modified;
+            if let Some(data) = cache.get(path, len, modified) {
+                return Ok(boxed(Full::from(data)));
+            }
+
+            let mut data = Vec::with_capacity(len as usize);
+            timing::fs_wait((&mut file).take(len + 1).read_to_end(&mut data)).await
+                .map_err(|err| AppError::from_io(err, path))?;
+            // A file that changed while being read is served but not kept
+            let changed = data.len() as u64 != len;
+            data.truncate(len as usize);
+            let data = Bytes::from(data);
+            if !changed {
+                cache.insert(path, len, modified, data.clone());
+            }
+            return Ok(boxed(Full::from(data)));
+        }
+
+        if self.mappable(stat) {
+            let std_file = file.into_std().await;
+            // SAFETY: touching a mapped page the file no longer covers raises
+            // SIGBUS, so only files nobody may write to are mapped. Their
+            // owner could still make them writable and truncate them, which
+            // is why mapping is opt-in
+            match unsafe { Mmap::map(&std_file) } {
+                Ok(mmap) if mmap.len() as u64 == len => return Ok(boxed(Full::from(Bytes::from_owner(mmap)))),
+                // The file changed or cannot be mapped; read it instead
+                _ => file = fs::File::from_std(std_file),
+            }
+        }
+
+        let reader = BufReader::with_capacity(self.read_buffer_size, TimedReader::new(file.take(len)));
+        Ok(boxed(StreamBody::new(ReaderStream::with_capacity(reader, self.chunk_size))))
+    }
+
+    // Whether to memory-map a file rather than read it
+    fn mappable(&self, stat: &Stat) -> bool {
+        stat.len > 0 && stat.len <= self.mmap_max_size && stat.readonly
+    }
+
+    // Forget anything cached for a file the server just wrote
+    pub fn invalidate(&self, path: &Path) {
+        if let Some(cache) = &self.cache {
+            cache.remove(path);
+        }
+    }
+}
+
+// Least recently used contents of small files, keyed by path and checked
+// against the file's size and modification time
+#[derive(Debug)]
+struct FileCache {
+    max_bytes: usize,
+    max_entries: usize,
+    max_file_size: u64,
+    inner: Mutex<CacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct CacheInner {
+    entries: HashMap<PathBuf, CachedFile>,
+    bytes: usize,
+    // Incremented on every access, to order entries by recency
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    len: u64,
+    modified: Option<SystemTime>,
+    data: Bytes,
+    last_used: u64,
+}
+
+impl FileCache {
+    fn get(&self, path: &Path, len: u64, modified: Option<SystemTime>) -> Option<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        let entry = inner.entries.get_mut(path)?;
+        if entry.len != len || entry.modified != modified {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.data.clone())
+    }
+
+    fn insert(&self, path: &Path, len: u64, modified: Option<SystemTime>, data: Bytes) {
+        if data.len() > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(stale) = inner.entries.remove(path) {
+            inner.bytes -= stale.data.len();
+        }
+        while inner.entries.len() >= self.max_entries || inner.bytes + data.len() > self.max_bytes {
+            let Some(oldest) = inner.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            let evicted = inner.entries.remove(&oldest).unwrap();
+            inner.bytes -= evicted.data.len();
+        }
+
+        inner.clock += 1;
+        let last_used = inner.clock;
+        inner.bytes += data.len();
+        inner.entries.insert(path.to_path_buf(), CachedFile { len, modified, data, last_used });
+    }
+
+    fn remove(&self, path: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.entries.remove(path) {
+            inner.bytes -= entry.data.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    // Serve a file that grows after it was stat'ed
+    async fn grown_body(name: &str, config: &FileConfig) -> Bytes {
+        let path = scratch_dir(name).join("grown.txt");
+        std::fs::write(&path, "stat").unwrap();
+        let stat = Stat::from(&std::fs::metadata(&path).unwrap());
+        std::fs::write(&path, "stat, then more").unwrap();
+
+        let file = fs::File::open(&path).await.unwrap();
+        let body = FileBodies::from_config(config).body(&path, file, &stat).await.unwrap();
+        hyper::body::to_bytes(body).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn streamed_bodies_keep_to_the_stat() {
+        let config = FileConfig { cache_max_bytes: 0, mmap_max_size: 0, ..FileConfig::default() };
+        assert_eq!(grown_body("files-streamed", &config).await, "stat");
+    }
+
+    #[tokio::test]
+    async fn cached_bodies_keep_to_the_stat() {
+        let config = FileConfig {
+            cache_max_bytes: 1 << 20,
+            cache_entries: 16,
+            cache_max_file_size: 1 << 10,
+            ..FileConfig::default()
+        };
+        assert_eq!(grown_body("files-cached", &config).await, "stat");
+    }
+
+    #[test]
+    fn only_read_only_files_are_mapped() {
+        let bodies = FileBodies::from_config(&FileConfig { mmap_max_size: 1024, ..FileConfig::default() });
+        let stat = Stat { is_file: true, is_dir: false, len: 100, modified: None, readonly: true, file_id: None };
+        assert!(bodies.mappable(&stat));
+        assert!(!bodies.mappable(&Stat { readonly: false, ..stat }));
+        assert!(!bodies.mappable(&Stat { len: 0, ..stat }));
+        assert!(!bodies.mappable(&Stat { len: 2048, ..stat }));
+    }
+
+    #[tokio::test]
+    async fn mapped_files_are_served_whole() {
+        let path = scratch_dir("files-mapped").join("mapped.txt");
+        std::fs::write(&path, "mapped").unwrap();
+        let mut permissions = std::fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&path, permissions).unwrap();
+        let stat = Stat::from(&std::fs::metadata(&path).unwrap());
+
+        let bodies = FileBodies::from_config(&FileConfig { mmap_max_size: 1024, ..FileConfig::default() });
+        assert!(bodies.mappable(&stat));
+        let body = bodies.body(&path, fs::File::open(&path).await.unwrap(), &stat).await.unwrap();
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "mapped");
+    }
+
+    #[tokio::test]
+    async fn cached_contents_need_the_same_version() {
+        let path = scratch_dir("files-cache-hit").join("cached.txt");
+        std::fs::write(&path, "cached").unwrap();
+        let stat = Stat::from(&std::fs::metadata(&path).unwrap());
+        let bodies = FileBodies::from_config(&FileConfig {
+            cache_max_bytes: 1 << 20,
+            cache_entries: 16,
+            ..FileConfig::default()
+        });
+
+        assert!(bodies.cached(&path, &stat).is_none());
+        bodies.body(&path, fs::File::open(&path).await.unwrap(), &stat).await.unwrap();
+        // Served from memory even once the file is gone
+        std::fs::remove_file(&path).unwrap();
+        let body = bodies.cached(&path, &stat).unwrap();
+        assert_eq!(hyper::body::to_bytes(body).await.unwrap(), "cached");
+        assert!(bodies.cached(&path, &Stat { len: 7, ..stat }).is_none());
+    }
+}
@@ -0,0 +1,217 @@
+use axum::{
+    extract::{multipart::{Field, MultipartError}, Multipart, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::{future::Future, path::PathBuf, sync::Arc, time::Duration};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    config::FormUploadConfig,
+    error::{self, AppError},
+    events::ChangeKind,
+    paths,
+    uploads::{self, UploadWriter, Uploads, PARTIAL_PREFIX},
+    state::AppState,
+};
+
+// Uploads from plain HTML forms and scripts: POST multipart/form-data to a
+// directory, each file part becoming a file in it
+#[derive(Debug)]
+pub struct FormUploads {
+    max_file_size: Option<u64>,
+    max_files: usize,
+}
+
+impl FormUploads {
+    pub fn from_config(config: &FormUploadConfig) -> FormUploads {
+        FormUploads {
+            max_file_size: (config.max_file_size > 0).then_some(config.max_file_size),
+            max_files: config.max_files,
+        }
+    }
+}
+
+pub async fn upload(
+    State(state): State<Arc<AppState>>,
+    path: Option<Path<String>>,
+    mut multipart: Multipart,
+) -> Result<Response, AppError> {
+    let Some(uploads) = &state.form_uploads else {
+        return Err(AppError::NotFound("form uploads".into()));
+    };
+    let paths = state.paths.get();
+    let dir = match &path {
+        Some(Path(path)) => paths.resolve_upload_dir(path).await?,
+        None => PathBuf::new(),
+    };
+    if !dir.as_os_str().is_empty() && !state.stats.stat(&dir).await?.is_dir {
+        return Err(AppError::Conflict(format!("{} is not a directory", dir.display())));
+    }
+
+    let stall = state.timeouts.body_read();
+    let mut created = Vec::new();
+    while let Some(field) = stalling(stall, multipart.next_field()).await?.map_err(|err| malformed(err, &dir))? {
+        // Parts without a file name are ordinary form fields
+        let Some(name) = field.file_name().map(file_name) else {
+            continue;
+        };
+        if created.len() == uploads.max_files {
+            return Err(AppError::Rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("At most {} files can be uploaded at once", uploads.max_files),
+            ));
+        }
+        let name = name.ok_or_else(|| AppError::InvalidPath("Part has no usable file name".into()))?;
+
+        let target = paths.resolve_new(&paths::glob_path(&dir.join(name))).await?;
+        if fs::symlink_metadata(&target).await.is_ok() {
+            return Err(AppError::Conflict(target.display().to_string()));
+        }
+        let handle = state.open_files.reserve().await;
+        store(field, &target, uploads.max_file_size, stall, &state.uploads).await?;
+        drop(handle);
+        state.changed(ChangeKind::Create, &target);
+        created.push(paths::glob_path(&target));
+    }
+
+    Ok((StatusCode::CREATED, Json(serde_json::json!({ "created": created }))).into_response())
+}
+
+// The last component of a client-supplied file name; browsers send bare
+// names, but some tools send whole paths with either separator
+fn file_name(name: &str) -> Option<&str> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("").trim();
+    (!name.is_empty() && name != "." && name != "..").then_some(name)
+}
+
+// Write a part under a temporary name, next to its target or in the
+// staging directory, then move it in place, so a failed upload never
+// leaves a partial file behind
+async fn store(
+    mut field: Field<'_>,
+    target: &std::path::Path,
+    max_size: Option<u64>,
+    stall: Option<Duration>,
+    uploads: &Uploads,
+) -> Result<(), AppError> {
+    let partial = uploads.partial_path(target, &format!("{}{}", PARTIAL_PREFIX, Uuid::new_v4()));
+    // Removes the partial file however the upload ends short of being moved
+    // in place, the request being dropped mid-body included
+    let mut guard = PartialFile(Some(partial.clone()));
+    let written = async {
+        let mut file = fs::File::create(&partial).await
+            .map_err(|err| AppError::from_io(err, &partial))?;
+        let mut writer = UploadWriter::new(&mut file, 0, uploads.sparse);
+        let mut size = 0u64;
+        while let Some(chunk) = stalling(stall, field.chunk()).await?.map_err(|err| malformed(err, target))? {
+            size += chunk.len() as u64;
+            if let Some(max_size) = max_size.filter(|max_size| size > *max_size) {
+                return Err(AppError::Rejected(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!("Files can be at most {} bytes", max_size),
+                ));
+            }
+            writer.write(&chunk).await
+                .map_err(|err| AppError::from_io(err, &partial))?;
+        }
+        writer.finish().await.map_err(|err| AppError::from_io(err, &partial))?;
+        uploads::move_into_place(&partial, target).await
+    };
+
+    let result = written.await;
+    if result.is_ok() {
+        guard.0 = None;
+    }
+    result
+}
+
+// Give up on clients that stop sending mid-body
+async fn stalling<T>(stall: Option<Duration>, read: impl Future<Output = T>) -> Result<T, AppError> {
+    match stall {
+        Some(stall) => tokio::time::timeout(stall, read).await
+            .map_err(|_| AppError::Rejected(StatusCode::REQUEST_TIMEOUT, "Upload body stalled".into())),
+        None => Ok(read.await),
+    }
+}
+
+struct PartialFile(Option<PathBuf>);
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if let Some(path) = self.0.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// A body that cannot be read as multipart. A client that went away is
+// logged as such, since the response will never reach it; multer hides
+// why reading the body failed, but without a body limit set that leaves
+// only the connection
+fn malformed(err: MultipartError, target: &std::path::Path) -> AppError {
+    if error::is_disconnect(&err) || err.status() == StatusCode::INTERNAL_SERVER_ERROR {
+        tracing::info!("Upload to /{} aborted by the client", paths::glob_path(target));
+    }
+    AppError::Rejected(StatusCode::BAD_REQUEST, format!("Malformed multipart body: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, testing::scratch_dir};
+    use axum::{body::Body, http::Request, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn stalled_reads_time_out() {
+        let stalled = stalling(Some(Duration::from_millis(10)), std::future::pending::<()>()).await;
+        assert!(matches!(stalled, Err(AppError::Rejected(StatusCode::REQUEST_TIMEOUT, _))));
+        assert!(matches!(stalling(None, async { 1 }).await, Ok(1)));
+    }
+
+    #[test]
+    fn file_names_lose_their_directories() {
+        assert_eq!(file_name("photo.jpg"), Some("photo.jpg"));
+        assert_eq!(file_name("C:\\Users\\me\\photo.jpg"), Some("photo.jpg"));
+        assert_eq!(file_name("../../etc/passwd"), Some("passwd"));
+        assert_eq!(file_name("  spaced.txt "), Some("spaced.txt"));
+        for name in ["", "dir/", "..", "a/..", ".", "  "] {
+            assert_eq!(file_name(name), None, "{:?}", name);
+        }
+    }
+
+    // POST a multipart body with one file part named `name` to `dir`
+    async fn upload_as(dir: &std::path::Path, name: &str) -> StatusCode {
+        let config = Config {
+            form_upload: FormUploadConfig { enabled: true, ..FormUploadConfig::default() },
+            ..Config::default()
+        };
+        let app = Router::new()
+            .route("/*path", post(upload))
+            .with_state(Arc::new(AppState::from_config(&config).unwrap()));
+        let body = format!(
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\ndata\r\n--b--\r\n",
+            name,
+        );
+        let req = Request::post(format!("/{}", paths::glob_path(dir)))
+            .header("content-type", "multipart/form-data; boundary=b")
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn uploads_stay_in_their_directory() {
+        let dir = scratch_dir("form-upload-names");
+        std::fs::create_dir_all(dir.join("inbox")).unwrap();
+
+        assert_eq!(upload_as(&dir.join("inbox"), "../../escaped.txt").await, StatusCode::CREATED);
+        assert_eq!(std::fs::read_to_string(dir.join("inbox/escaped.txt")).unwrap(), "data");
+        assert!(!dir.join("escaped.txt").exists());
+        assert_eq!(upload_as(&dir.join("inbox"), "..").await, StatusCode::BAD_REQUEST);
+        assert_eq!(upload_as(&dir.join("inbox"), "escaped.txt").await, StatusCode::CONFLICT);
+    }
+}
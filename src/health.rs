@@ -0,0 +1,92 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    routing::get,
+    Router,
+};
+use std::{
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+use tokio::fs;
+
+use crate::{error::AppError, state::AppState};
+
+// Liveness and readiness probes for load balancers and orchestrators
+pub fn routes(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/healthz", get(alive))
+        .route("/readyz", get(ready))
+        .with_state(state)
+}
+
+// The process is up and answering requests
+async fn alive() -> &'static str {
+    "ok"
+}
+
+// The served directory, and the tus staging area if enabled, are usable
+async fn ready(State(state): State<Arc<AppState>>) -> Result<&'static str, AppError> {
+    if state.draining.load(Ordering::Relaxed) {
+        return Err(AppError::Rejected(StatusCode::SERVICE_UNAVAILABLE, "Draining".into()));
+    }
+
+    let mut dirs = vec![Path::new(".")];
+    if let Some(tus) = &state.tus {
+        dirs.push(tus.staging_dir());
+    }
+
+    for dir in dirs {
+        if let Err(err) = fs::read_dir(dir).await {
+            tracing::warn!("Readiness check failed for {}: {}", dir.display(), err);
+            return Err(AppError::Rejected(
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("{} is not accessible", dir.display()),
+            ));
+        }
+    }
+    Ok("ok")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, TusConfig, UploadConfig},
+        testing::scratch_dir,
+    };
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    async fn status(state: &Arc<AppState>, uri: &str) -> StatusCode {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        routes(state.clone()).oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn readiness_needs_the_staging_area() {
+        let staging = scratch_dir("health_staging");
+        let state = Arc::new(AppState::from_config(&Config {
+            tus: TusConfig { enabled: true, ..TusConfig::default() },
+            uploads: UploadConfig { staging_dir: Some(staging.clone()), ..UploadConfig::default() },
+            ..Config::default()
+        }).unwrap());
+        assert_eq!(status(&state, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(&state, "/readyz").await, StatusCode::OK);
+
+        std::fs::remove_dir_all(&staging).unwrap();
+        assert_eq!(status(&state, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(&state, "/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn draining_servers_are_not_ready() {
+        let state = Arc::new(AppState::from_config(&Config::default()).unwrap());
+        state.draining.store(true, Ordering::Relaxed);
+        assert_eq!(status(&state, "/healthz").await, StatusCode::OK);
+        assert_eq!(status(&state, "/readyz").await, StatusCode::SERVICE_UNAVAILABLE);
+
+        state.draining.store(false, Ordering::Relaxed);
+        assert_eq!(status(&state, "/readyz").await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use axum::http::{header, HeaderMap, StatusCode};
+use serde::Deserialize;
+
+use crate::config::ConfigError;
+
+// Translations of the strings in HTML error pages, one per language. The
+// built-in strings are English, which is also what any string missing from
+// a catalog falls back to
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Catalog {
+    // Label in front of the request ID
+    request_id: Option<String>,
+    // Reason phrases by status code, e.g. "404"
+    reasons: HashMap<String, String>,
+    // Error messages by kind, e.g. "not-found", with {path}, {reason},
+    // {method} and {allow} placeholders
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn reason(&self, status: StatusCode) -> Option<&str> {
+        self.reasons.get(status.as_str()).map(String::as_str)
+    }
+
+    // The message of kind `key` with its placeholders filled in
+    pub fn message(&self, key: &str, args: &[(&str, String)]) -> Option<String> {
+        let template = self.messages.get(key)?;
+        Some(substitute(template, "{", "}", |name| {
+            args.iter().find(|(arg, _)| *arg == name).map(|(_, value)| value.clone())
+        }))
+    }
+}
+
+// Fill in the placeholders of `template`, names between `open` and `close`,
+// in one pass, so values that look like placeholders (a path such as
+// "{{request_id}}") stay as they are. Unknown names are left alone
+pub fn substitute(
+    template: &str,
+    open: &str,
+    close: &str,
+    value: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find(open) {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + open.len()..];
+        match after.find(close).and_then(|end| Some((end, value(&after[..end])?))) {
+            Some((end, value)) => {
+                filled.push_str(&value);
+                rest = &after[end + close.len()..];
+            }
+            None => {
+                filled.push_str(open);
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+// Catalogs loaded from a directory of <language>.toml files
+#[derive(Debug, Default)]
+pub struct Catalogs {
+    // Keyed by lowercase language tag, e.g. "de" or "pt-br"
+    languages: HashMap<String, Catalog>,
+}
+
+impl Catalogs {
+    pub fn load(dir: &Path) -> Result<Catalogs, ConfigError> {
+        let mut languages = HashMap::new();
+        let entries = fs::read_dir(dir).map_err(|err| ConfigError::Io(dir.into(), err))?;
+        for entry in entries {
+            let path = entry.map_err(|err| ConfigError::Io(dir.into(), err))?.path();
+            if path.extension().is_none_or(|extension| extension != "toml") {
+                continue;
+            }
+            let Some(language) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let text = fs::read_to_string(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+            let catalog = toml::from_str(&text).map_err(|err| ConfigError::Parse(path.clone(), err))?;
+            languages.insert(language.to_ascii_lowercase(), catalog);
+        }
+        Ok(Catalogs { languages })
+    }
+
+    // The language and catalog the client prefers by its Accept-Language
+    // header, or None for English. A catalog for "de" also serves "de-CH"
+    pub fn negotiate(&self, headers: &HeaderMap) -> Option<(&str, &Catalog)> {
+        if self.languages.is_empty() {
+            return None;
+        }
+        let accept = headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+
+        let mut best: Option<(&str, &Catalog)> = None;
+        let mut best_quality = 0.0;
+        for item in accept.split(',') {
+            let mut params = item.split(';');
+            let tag = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality <= best_quality {
+                continue;
+            }
+
+            let primary = tag.split('-').next().unwrap_or("");
+            // English is built in
+            if primary == "en" {
+                best = None;
+                best_quality = quality;
+                continue;
+            }
+            let found = self.languages.get_key_value(&tag)
+                .or_else(|| self.languages.get_key_value(primary));
+            if let Some((language, catalog)) = found {
+                best = Some((language.as_str(), catalog));
+                best_quality = quality;
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_fills_each_placeholder_once() {
+        let value = |name: &str| match name {
+            "path" => Some("{method}".to_string()),
+            "method" => Some("GET".to_string()),
+            _ => None,
+        };
+        assert_eq!(substitute("{path} via {method}", "{", "}", value), "{method} via GET");
+        assert_eq!(substitute("{unknown} {{path}} {", "{", "}", value), "{unknown} {{method}} {");
+    }
+
+    #[test]
+    fn catalog_messages_take_their_arguments() {
+        let catalog: Catalog = toml::from_str("[messages]\nnot-found = \"Nicht gefunden: {path}\"").unwrap();
+        let message = catalog.message("not-found", &[("path", "a/{path}.txt".into())]);
+        assert_eq!(message.as_deref(), Some("Nicht gefunden: a/{path}.txt"));
+        assert_eq!(catalog.message("conflict", &[]), None);
+    }
+}
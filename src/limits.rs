@@ -0,0 +1,290 @@
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::{
+    body::{boxed, BoxBody, Bytes, HttpBody},
+    extract::{ConnectInfo, State},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body::SizeHint;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    archive, config::LimitConfig, digest, error::AppError, file_info, listener::ConnectionInfo,
+    state::AppState,
+};
+
+// Middleware refusing requests with too many header fields, closing the
+// connection so the client cannot keep trying on it
+pub async fn check_headers<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if req.headers().len() > state.limits.max_headers {
+        let mut response = AppError::Rejected(
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            format!("Requests may carry at most {} headers", state.limits.max_headers),
+        ).into_response();
+        response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+        return response;
+    }
+
+    next.run(req).await
+}
+
+// Middleware asking the client to close the connection after this
+// response once the connection has served its share: it is too old, it
+// carried too many requests, or the server is draining. Connections
+// sitting idle stay open until their next request or the idle timeout
+pub async fn limit_connection_lifetime<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let served = connection.requests.fetch_add(1, Ordering::Relaxed) + 1;
+    let mut response = next.run(req).await;
+
+    let limits = &state.limits;
+    let spent = state.draining.load(Ordering::Relaxed)
+        || (limits.max_connection_requests > 0 && served >= limits.max_connection_requests)
+        || (limits.max_connection_age_secs > 0
+            && connection.opened.elapsed() >= Duration::from_secs(limits.max_connection_age_secs));
+    if spent {
+        response.headers_mut().insert(header::CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+// Caps on requests in flight, overall and per cost class
+#[derive(Debug)]
+pub struct ConcurrencyLimits {
+    all: Option<Arc<Semaphore>>,
+    cheap: Option<Arc<Semaphore>>,
+    expensive: Option<Arc<Semaphore>>,
+    retry_after: u64,
+    queue_wait: Option<Duration>,
+    max_queued: usize,
+    queued: AtomicUsize,
+}
+
+impl ConcurrencyLimits {
+    pub fn from_config(config: &LimitConfig) -> ConcurrencyLimits {
+        let semaphore = |max: usize| (max > 0).then(|| Arc::new(Semaphore::new(max)));
+        ConcurrencyLimits {
+            all: semaphore(config.max_requests),
+            cheap: semaphore(config.max_cheap_requests),
+            expensive: semaphore(config.max_expensive_requests),
+            retry_after: config.retry_after_secs,
+            queue_wait: (config.queue_wait_ms > 0).then(|| Duration::from_millis(config.queue_wait_ms)),
+            max_queued: config.max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    fn semaphores(&self, cost: Cost) -> impl Iterator<Item = &Arc<Semaphore>> {
+        let class = match cost {
+            Cost::Cheap => &self.cheap,
+            Cost::Expensive => &self.expensive,
+        };
+        [&self.all, class].into_iter().flatten()
+    }
+
+    // Take a slot in the global and class limits, or None if either is full
+    fn try_acquire(&self, cost: Cost) -> Option<Vec<OwnedSemaphorePermit>> {
+        self.semaphores(cost)
+            .map(|semaphore| semaphore.clone().try_acquire_owned().ok())
+            .collect()
+    }
+
+    // Like `try_acquire`, but wait up to the queue wait for slots to free
+    // up, unless too many requests are waiting already
+    async fn acquire(&self, cost: Cost) -> Option<Vec<OwnedSemaphorePermit>> {
+        if let Some(permits) = self.try_acquire(cost) {
+            return Some(permits);
+        }
+        let wait = self.queue_wait?;
+        if self.queued.fetch_add(1, Ordering::Relaxed) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let acquired = tokio::time::timeout(wait, async {
+            let mut permits = Vec::new();
+            for semaphore in self.semaphores(cost) {
+                permits.push(semaphore.clone().acquire_owned().await.ok()?);
+            }
+            Some(permits)
+        }).await;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        acquired.ok().flatten()
+    }
+}
+
+// Cost class of a request, judged by the work its head asks for rather
+// than its method alone: metadata is cheap even with GET, while archives,
+// checksums and previews are built even for HEAD, and with content ETags
+// any file may have to be hashed first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cost {
+    Cheap,
+    Expensive,
+}
+
+impl Cost {
+    fn of<B>(req: &Request<B>, hashes_content: bool) -> Cost {
+        let query = req.uri().query();
+        let headers = req.headers();
+        let expensive = match *req.method() {
+            Method::OPTIONS => false,
+            Method::GET | Method::HEAD if file_info::requested(query) => hashes_content,
+            Method::HEAD => {
+                hashes_content
+                    || archive::Format::requested(query, headers).is_some()
+                    || matches!(digest::Algorithm::requested(query), Ok(Some(_)))
+                    || digest::Algorithm::from_want_digest(headers).is_some()
+                    || query.is_some_and(|query| query.split('&').any(|pair| pair.starts_with("thumb=")))
+            }
+            _ => true,
+        };
+        match expensive {
+            true => Cost::Expensive,
+            false => Cost::Cheap,
+        }
+    }
+}
+
+// Middleware shedding requests beyond the concurrency limits with 503,
+// after letting them queue for a while if configured. A slot stays taken
+// until the response body has been sent
+pub async fn limit_concurrency<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let limits = &state.concurrency;
+    let Some(permits) = limits.acquire(Cost::of(&req, state.etags.hashes_content())).await else {
+        let mut response = AppError::Rejected(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is busy, try again later".into(),
+        ).into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, limits.retry_after.into());
+        return response;
+    };
+
+    next.run(req).await.map(|body| guarded(body, permits))
+}
+
+// Soft cap on files held open at once. Each request takes one handle for
+// all the files it reads or writes, so one hashing a file it also serves
+// does not wait on itself; past the cap, requests wait for a handle
+#[derive(Debug, Clone, Default)]
+pub struct OpenFiles(Option<Arc<Semaphore>>);
+
+impl OpenFiles {
+    pub fn new(max: usize) -> OpenFiles {
+        OpenFiles((max > 0).then(|| Arc::new(Semaphore::new(max))))
+    }
+
+    // Wait for a handle, released when the returned guard is dropped
+    pub async fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        self.0.clone()?.acquire_owned().await.ok()
+    }
+}
+
+// Body holding on to `guard` until the response is sent or dropped
+pub fn guarded<G: Unpin + Send + 'static>(body: BoxBody, guard: G) -> BoxBody {
+    boxed(GuardedBody { inner: body, _guard: guard })
+}
+
+// Response body holding on to a guard until it is dropped
+struct GuardedBody<B, G> {
+    inner: B,
+    _guard: G,
+}
+
+impl<B, G> HttpBody for GuardedBody<B, G>
+where
+    B: HttpBody<Data = Bytes, Error = axum::Error> + Unpin,
+    G: Unpin,
+{
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+        let mut req = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(()).unwrap()
+    }
+
+    fn cost(method: Method, uri: &str, headers: &[(&str, &str)]) -> Cost {
+        Cost::of(&request(method, uri, headers), false)
+    }
+
+    #[test]
+    fn metadata_requests_are_cheap() {
+        assert_eq!(cost(Method::HEAD, "/a.txt", &[]), Cost::Cheap);
+        assert_eq!(cost(Method::OPTIONS, "/", &[]), Cost::Cheap);
+        assert_eq!(cost(Method::GET, "/a.txt?stat=json", &[]), Cost::Cheap);
+        assert_eq!(cost(Method::GET, "/dir?accept=zip&stat=json", &[]), Cost::Cheap);
+    }
+
+    #[test]
+    fn requests_building_content_are_expensive() {
+        assert_eq!(cost(Method::GET, "/a.txt", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::PUT, "/a.txt", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::GET, "/dir?accept=zip", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::HEAD, "/dir?accept=tar.gz", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::HEAD, "/dir", &[("accept", "application/zip")]), Cost::Expensive);
+        assert_eq!(cost(Method::HEAD, "/a.txt?checksum=sha256", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::HEAD, "/a.png?thumb=128", &[]), Cost::Expensive);
+        assert_eq!(cost(Method::HEAD, "/a.txt", &[("want-digest", "sha-256")]), Cost::Expensive);
+    }
+
+    #[test]
+    fn content_etags_make_metadata_expensive() {
+        for (method, uri) in [(Method::HEAD, "/a.txt"), (Method::GET, "/a.txt?stat=json")] {
+            assert_eq!(Cost::of(&request(method, uri, &[]), true), Cost::Expensive, "{}", uri);
+        }
+        assert_eq!(Cost::of(&request(Method::OPTIONS, "/a.txt", &[]), true), Cost::Cheap);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, TcpListener},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{connect_info::Connected, ConnectInfo},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream},
+};
+use serde::Serialize;
+use socket2::{Domain, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_io_timeout::TimeoutStream;
+
+use crate::{
+    config::{Config, SocketConfig},
+    limits,
+    metrics::Metrics,
+    state::AppState,
+    throttle::{RateLimiter, Throttle},
+};
+
+// Accepts TCP connections and applies per-connection IO timeouts and
+// bandwidth limits
+pub struct Listener {
+    incoming: AddrIncoming,
+    start_timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    // Shared by all connections
+    upload_limit: Option<Arc<RateLimiter>>,
+    download_limit: Option<Arc<RateLimiter>>,
+    // Bytes per second allowed on each connection
+    connection_upload: u64,
+    connection_download: u64,
+    metrics: Option<Arc<Metrics>>,
+    connections: Option<Arc<Connections>>,
+}
+
+impl Listener {
+    pub fn bind(addr: &SocketAddr, config: &Config, state: &AppState) -> io::Result<Listener> {
+        let (timeouts, bandwidth) = (&config.timeouts, &config.bandwidth);
+        let listener = tokio::net::TcpListener::from_std(listen(addr, &config.socket)?)?;
+        let mut incoming = AddrIncoming::from_listener(listener).map_err(io::Error::other)?;
+        incoming
+            .set_nodelay(config.socket.nodelay)
+            .set_keepalive(config.socket.keepalive())
+            .set_keepalive_interval(config.socket.keepalive_interval())
+            .set_keepalive_retries(config.socket.keepalive_retries());
+
+        Ok(Listener {
+            incoming,
+            start_timeout: timeouts.request_start(),
+            idle_timeout: timeouts.idle(),
+            write_timeout: timeouts.body_write(),
+            upload_limit: RateLimiter::new(bandwidth.upload_bytes_per_sec),
+            download_limit: RateLimiter::new(bandwidth.download_bytes_per_sec),
+            connection_upload: bandwidth.connection_upload_bytes_per_sec,
+            connection_download: bandwidth.connection_download_bytes_per_sec,
+            metrics: state.metrics.clone(),
+            connections: state.connections.clone(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+}
+
+// Bind a listening socket with the configured backlog and options
+fn listen(addr: &SocketAddr, config: &SocketConfig) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+    #[cfg(unix)]
+    {
+        // Like std, allow rebinding while old connections linger in TIME_WAIT
+        socket.set_reuse_address(true)?;
+        if config.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+    }
+    socket.bind(&(*addr).into())?;
+    socket.listen(config.backlog.try_into().unwrap_or(i32::MAX))?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+impl Accept for Listener {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Connection, io::Error>>> {
+        let this = &mut *self;
+        let (start_timeout, idle_timeout, write_timeout) =
+            (this.start_timeout, this.idle_timeout, this.write_timeout);
+        Pin::new(&mut this.incoming).poll_accept(cx).map_ok(|stream| {
+            let remote_addr = stream.remote_addr();
+            let mut stream = TimeoutStream::new(stream);
+            // Clients that connect and send nothing get the shorter start
+            // timeout; see Connection::poll_read
+            stream.set_read_timeout(start_timeout);
+            // A write pending this long means the client stopped reading
+            stream.set_write_timeout(write_timeout);
+            if let Some(metrics) = &this.metrics {
+                metrics.connection_opened();
+            }
+            let tracked = this.connections.as_ref()
+                .map(|connections| (connections.clone(), connections.open(remote_addr)));
+            Connection {
+                stream: Box::pin(stream),
+                read_timeout: start_timeout,
+                idle_timeout,
+                started: false,
+                in_flight: Arc::default(),
+                remote_addr,
+                upload: Throttle::new([
+                    this.upload_limit.clone(),
+                    RateLimiter::new(this.connection_upload),
+                ]),
+                download: Throttle::new([
+                    this.download_limit.clone(),
+                    RateLimiter::new(this.connection_download),
+                ]),
+                metrics: this.metrics.clone(),
+                tracked,
+            }
+        })
+    }
+}
+
+// An accepted connection with its IO timeouts and bandwidth limits
+pub struct Connection {
+    stream: Pin<Box<TimeoutStream<AddrStream>>>,
+    // Read timeout currently set on the stream
+    read_timeout: Option<Duration>,
+    // Read timeout once the client sent its first bytes, while no request
+    // is in flight
+    idle_timeout: Option<Duration>,
+    started: bool,
+    // Requests whose responses are not done yet; see track_requests
+    in_flight: Arc<AtomicUsize>,
+    remote_addr: SocketAddr,
+    upload: Throttle,
+    download: Throttle,
+    metrics: Option<Arc<Metrics>>,
+    // Registry entry to remove when the connection closes
+    tracked: Option<(Arc<Connections>, u64)>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.connection_closed();
+        }
+        if let Some((connections, id)) = &self.tracked {
+            connections.close(*id);
+        }
+    }
+}
+
+// Registry of open connections, for inspection
+#[derive(Debug, Default)]
+pub struct Connections {
+    next_id: AtomicU64,
+    open: Mutex<HashMap<u64, OpenConnection>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenConnection {
+    remote_addr: SocketAddr,
+    // Seconds since the Unix epoch
+    opened_at: u64,
+}
+
+impl Connections {
+    fn open(&self, remote_addr: SocketAddr) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let opened_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+        self.open.lock().unwrap().insert(id, OpenConnection { remote_addr, opened_at });
+        id
+    }
+
+    fn close(&self, id: u64) {
+        self.open.lock().unwrap().remove(&id);
+    }
+
+    pub fn list(&self) -> Vec<OpenConnection> {
+        self.open.lock().unwrap().values().cloned().collect()
+    }
+}
+
+// What requests know about the connection they arrived on
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub remote_addr: SocketAddr,
+    pub opened: Instant,
+    // Requests received so far, shared by all of the connection's requests
+    pub requests: Arc<AtomicU64>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Connected<&Connection> for ConnectionInfo {
+    fn connect_info(target: &Connection) -> ConnectionInfo {
+        ConnectionInfo {
+            remote_addr: target.remote_addr,
+            opened: Instant::now(),
+            requests: Arc::default(),
+            in_flight: target.in_flight.clone(),
+        }
+    }
+}
+
+// Middleware counting the connection's requests until their responses are
+// done, so the idle timeout only runs between requests
+pub async fn track_requests<B>(
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    connection.in_flight.fetch_add(1, Ordering::Relaxed);
+    let in_flight = InFlight(connection.in_flight);
+    next.run(req).await.map(|body| limits::guarded(body, in_flight))
+}
+
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        // hyper keeps reading while it handles a request and sends its
+        // response, so the idle timeout must not run then: a long download
+        // sends nothing the other way. Stalled bodies are left to the body
+        // read and write timeouts
+        let read_timeout = match (this.started, this.in_flight.load(Ordering::Relaxed)) {
+            (false, _) => this.read_timeout,
+            (true, 0) => this.idle_timeout,
+            (true, _) => None,
+        };
+        if read_timeout != this.read_timeout {
+            this.read_timeout = read_timeout;
+            this.stream.as_mut().set_read_timeout_pinned(read_timeout);
+        }
+
+        let filled = buf.filled().len();
+        let poll = if this.upload.is_active() {
+            let Poll::Ready(allowed) = this.upload.poll_allowance(cx, buf.remaining()) else {
+                return Poll::Pending;
+            };
+            // Read into a window no larger than the allowance
+            let mut limited = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+            let poll = this.stream.as_mut().poll_read(cx, &mut limited);
+            let read = limited.filled().len();
+            buf.advance(read);
+            this.upload.consume(read);
+            poll
+        } else {
+            this.stream.as_mut().poll_read(cx, buf)
+        };
+
+        if let Some(metrics) = &this.metrics {
+            metrics.received(buf.filled().len() - filled);
+        }
+
+        // Clients that connect and send nothing get the shorter start
+        // timeout, everyone else the idle timeout
+        if buf.filled().len() > filled && !this.started {
+            this.started = true;
+            this.read_timeout = this.idle_timeout;
+            this.stream.as_mut().set_read_timeout_pinned(this.idle_timeout);
+        }
+        poll
+    }
+}
+
+impl Connection {
+    fn count_sent(&self, poll: &Poll<io::Result<usize>>) {
+        if let (Some(metrics), Poll::Ready(Ok(written))) = (&self.metrics, poll) {
+            metrics.sent(*written);
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if !this.download.is_active() {
+            let poll = this.stream.as_mut().poll_write(cx, buf);
+            this.count_sent(&poll);
+            return poll;
+        }
+
+        let Poll::Ready(allowed) = this.download.poll_allowance(cx, buf.len()) else {
+            return Poll::Pending;
+        };
+        let poll = this.stream.as_mut().poll_write(cx, &buf[..allowed]);
+        if let Poll::Ready(Ok(written)) = poll {
+            this.download.consume(written);
+        }
+        this.count_sent(&poll);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.stream.as_mut().poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        if self.download.is_active() {
+            let buf = bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &buf[..]);
+            return self.poll_write(cx, buf);
+        }
+        let poll = self.stream.as_mut().poll_write_vectored(cx, bufs);
+        self.count_sent(&poll);
+        poll
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        // Throttled writes go out one buffer at a time
+        !self.download.is_active() && self.stream.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::{Bytes, StreamBody}, routing::get, Router};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    // Serve a response trickling out over `chunks` times 500 ms, with a
+    // 1 second idle timeout
+    async fn serve_slow_response(chunks: usize) -> SocketAddr {
+        let mut config = Config::default();
+        config.timeouts.idle_secs = 1;
+        serve(config, chunks).await
+    }
+
+    async fn serve(config: Config, chunks: usize) -> SocketAddr {
+        let state = AppState::from_config(&config).unwrap();
+        let listener = Listener::bind(&"127.0.0.1:0".parse().unwrap(), &config, &state).unwrap();
+        let addr = listener.local_addr();
+
+        let app = Router::new()
+            .route("/", get(move || async move {
+                StreamBody::new(futures_util::stream::unfold(0, move |sent| async move {
+                    if sent == chunks {
+                        return None;
+                    }
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    Some((Ok::<_, io::Error>(Bytes::from_static(b"chunk")), sent + 1))
+                }))
+            }))
+            .layer(axum::middleware::from_fn(track_requests));
+        tokio::spawn(axum::Server::builder(listener)
+            .serve(app.into_make_service_with_connect_info::<ConnectionInfo>()));
+        addr
+    }
+
+    async fn read_until_end_of_body(stream: &mut tokio::net::TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+        while !response.ends_with(b"0\r\n\r\n") {
+            let read = stream.read(&mut buf).await.unwrap();
+            assert!(read > 0, "connection closed mid-response: {:?}", String::from_utf8_lossy(&response));
+            response.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_spares_responses_in_progress() {
+        let addr = serve_slow_response(6).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+
+        let response = read_until_end_of_body(&mut stream).await;
+        assert_eq!(response.matches("\r\nchunk\r\n").count(), 6);
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_closes_connections_between_requests() {
+        let addr = serve_slow_response(1).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n").await.unwrap();
+        read_until_end_of_body(&mut stream).await;
+
+        let closed = tokio::time::timeout(Duration::from_secs(3), stream.read(&mut [0; 16])).await;
+        assert!(matches!(closed, Ok(Ok(0))), "idle connection stayed open");
+    }
+
+    #[tokio::test]
+    async fn start_timeout_closes_silent_connections() {
+        let mut config = Config::default();
+        config.timeouts.request_start_secs = 1;
+        let addr = serve(config, 1).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+        let closed = tokio::time::timeout(Duration::from_secs(3), stream.read(&mut [0; 16])).await;
+        assert!(matches!(closed, Ok(Ok(0))), "silent connection stayed open");
+    }
+
+    #[tokio::test]
+    async fn start_timeout_ends_with_the_first_byte() {
+        let mut config = Config::default();
+        config.timeouts.request_start_secs = 1;
+        config.timeouts.idle_secs = 5;
+        let addr = serve(config, 1).await;
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        stream.write_all(b"Host: test\r\n\r\n").await.unwrap();
+
+        let response = read_until_end_of_body(&mut stream).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+}
@@ -0,0 +1,102 @@
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use percent_encoding::percent_decode_str;
+use std::{sync::OnceLock, time::Instant};
+use tracing::Instrument as _;
+use tracing_subscriber::{
+    layer::SubscriberExt as _, reload, util::SubscriberInitExt as _, EnvFilter, Registry,
+};
+
+use crate::{
+    config::{ConfigError, LoggingConfig, OtelConfig, OutputFormat},
+    request_id::RequestId,
+};
+
+// Lets the filter be swapped when the configuration is reloaded
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+// Install the global subscriber
+pub fn init(
+    cli_filter: Option<&str>,
+    config: &LoggingConfig,
+    otel: &OtelConfig,
+) -> Result<(), ConfigError> {
+    let (filter, handle) = reload::Layer::new(filter(cli_filter, config)?);
+    let _ = FILTER.set(handle);
+
+    let (text, json) = match config.format {
+        OutputFormat::Text => (Some(tracing_subscriber::fmt::layer()), None),
+        OutputFormat::Json => (None, Some(tracing_subscriber::fmt::layer().json())),
+    };
+
+    #[cfg(feature = "otel")]
+    let otel = match otel.enabled {
+        true => Some(crate::otel::layer(otel)?),
+        false => None,
+    };
+    #[cfg(not(feature = "otel"))]
+    let otel = match otel.enabled {
+        true => return Err(ConfigError::Invalid("otel.enabled needs a build with the otel feature".into())),
+        false => None::<tracing_subscriber::layer::Identity>,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .with(otel)
+        .init();
+    Ok(())
+}
+
+// Apply a new filter to the running subscriber
+pub fn set_filter(filter: EnvFilter) {
+    if let Some(handle) = FILTER.get() {
+        if let Err(err) = handle.reload(filter) {
+            tracing::warn!("Failed to apply log filter: {}", err);
+        }
+    }
+}
+
+// The filter in effect. RUST_LOG wins over the command line, which wins
+// over the config file
+pub fn filter(cli_filter: Option<&str>, config: &LoggingConfig) -> Result<EnvFilter, ConfigError> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(filter) => filter,
+        Err(_) => cli_filter.unwrap_or(&config.filter).to_string(),
+    };
+    EnvFilter::try_new(&filter)
+        .map_err(|err| ConfigError::Invalid(format!("invalid log filter {:?}: {}", filter, err)))
+}
+
+// Middleware running each request inside a span naming it
+pub async fn trace_requests<B>(req: Request<B>, next: Next<B>) -> Response {
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy().into_owned();
+    let request_id = req.extensions().get::<RequestId>().map(|id| id.0.clone());
+    let span = tracing::info_span!(
+        "request",
+        id = request_id,
+        method = %req.method(),
+        path,
+        status = tracing::field::Empty,
+    );
+
+    #[cfg(feature = "otel")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt as _;
+        span.set_parent(crate::otel::parent_context(req.headers()));
+    }
+
+    async move {
+        let started = Instant::now();
+        let response = next.run(req).await;
+        tracing::Span::current().record("status", response.status().as_u16());
+        tracing::debug!(elapsed_ms = started.elapsed().as_millis() as u64, "response ready");
+        response
+    }
+    .instrument(span)
+    .await
+}
@@ -1,108 +1,684 @@
+mod access_log;
+mod admin;
+mod archive;
+mod cache_control;
+mod config;
+mod cors;
+#[cfg(unix)]
+mod daemon;
+mod digest;
+mod error;
+mod etag;
+mod events;
+mod file_info;
+mod files;
+mod form_upload;
+mod health;
+mod i18n;
+mod limits;
+mod listener;
+mod metrics;
+mod logging;
+mod mime_types;
+#[cfg(feature = "otel")]
+mod otel;
+mod paths;
+mod preconditions;
+mod proxies;
+mod rate_limit;
+mod security_headers;
+mod request_id;
+mod response_headers;
+mod search;
+#[cfg(windows)]
+mod service;
+mod stat_cache;
+mod state;
+#[cfg(test)]
+mod testing;
+mod timeouts;
+mod throttle;
+mod thumbnails;
+mod timing;
+mod tus;
+mod uploads;
+mod watcher;
+
 use axum::{
-    body::StreamBody,
-    routing::get,
+    body::{boxed, BoxBody, Empty},
+    extract::DefaultBodyLimit,
+    routing::{get, post},
     Router,
-    extract::Path,
+    extract::{Path, RawQuery, State},
+    middleware,
     response::{IntoResponse, Response},
-    http::{StatusCode, header},
+    http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, header},
+};
+use clap::{Parser, Subcommand};
+use std::{
+    future::{ready, Ready},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
 };
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
-use tokio::{fs, io::BufReader, signal};
-use tokio_util::io::ReaderStream;
-use tower_http::timeout::TimeoutLayer;
-
-// Custom error type for our application
-#[derive(Debug)]
-enum AppError {
-    NotFound(String),
-    IoError(std::io::Error),
-    InvalidPath(String),
+use tokio::{fs, runtime::Runtime, signal};
+
+use config::{Config, OutputFormat, RuntimeConfig};
+use error::AppError;
+use listener::{ConnectionInfo, Listener};
+use paths::PathResolver;
+use stat_cache::Stat;
+use state::AppState;
+
+#[derive(Debug, Clone, Parser)]
+#[command(version)]
+struct Cli {
+    /// Path to a TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Log filter in RUST_LOG syntax, overriding the config file
+    #[arg(long)]
+    log_filter: Option<String>,
+    /// Output format of the diagnostic and access logs, overriding the config file
+    #[arg(long, value_enum)]
+    log_format: Option<OutputFormat>,
+    /// Detach from the terminal and run in the background; diagnostic
+    /// output is discarded, so log requests to a file
+    #[cfg(unix)]
+    #[arg(long)]
+    daemonize: bool,
+    /// Write the process ID to this file, and remove it on exit; refuses to
+    /// start while another running server holds it
+    #[cfg(unix)]
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-// Implement error responses
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match self {
-            AppError::NotFound(path) => 
-                (StatusCode::NOT_FOUND, format!("File not found: {}", path)),
-            AppError::IoError(err) => 
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("Server error: {}", err)),
-            AppError::InvalidPath(path) => 
-                (StatusCode::BAD_REQUEST, format!("Invalid path: {}", path)),
-        };
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    /// Check the configuration and the directories it uses, then exit
+    ///
+    /// Exits with 1 if the configuration is invalid and 2 if a directory
+    /// cannot be read.
+    CheckConfig,
+    /// Run under the Windows service control manager
+    ///
+    /// Register the service with, for example:
+    /// sc.exe create axum-webdav start= auto
+    ///   binPath= "C:\axum-webdav\axum-webdav.exe --config C:\axum-webdav\config.toml service C:\srv"
+    #[cfg(windows)]
+    Service {
+        /// Directory to serve; services start in the system directory
+        directory: PathBuf,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
 
-        (status, message).into_response()
+    // Logging is configured by the file, so errors loading it go to stderr
+    let mut config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Config error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Some(format) = cli.log_format {
+        config.logging.format = format;
     }
+
+    // Detach before the runtime starts its threads
+    #[cfg(unix)]
+    let _pid_file = match cli.command {
+        Some(Command::CheckConfig) => None,
+        None => daemonize(&cli),
+    };
+
+    // The service control manager runs the server on a thread of its own
+    #[cfg(windows)]
+    if let Some(Command::Service { directory }) = &cli.command {
+        if let Err(err) = std::env::set_current_dir(directory) {
+            eprintln!("Cannot serve {}: {}", directory.display(), err);
+            std::process::exit(1);
+        }
+        if let Err(err) = service::run(cli.clone(), config) {
+            eprintln!("Service error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let runtime = build_runtime(&config.runtime).expect("failed to start the async runtime");
+    runtime.block_on(async {
+        if let Some(Command::CheckConfig) = cli.command {
+            std::process::exit(check_config(&cli, &config).await);
+        }
+        serve(cli, config).await;
+    });
 }
 
-#[tokio::main]
-async fn main() {
+// Detach if asked to and write the PID file, exiting on failure
+#[cfg(unix)]
+fn daemonize(cli: &Cli) -> Option<daemon::PidFile> {
+    let fail = |what: &str, err: &dyn std::fmt::Display| -> ! {
+        eprintln!("{}: {}", what, err);
+        std::process::exit(1);
+    };
+
+    let mut pid_file = cli.pid_file.as_deref().map(|path| {
+        daemon::PidFile::create(path)
+            .unwrap_or_else(|err| fail(&format!("Cannot create {}", path.display()), &err))
+    });
+    if cli.daemonize {
+        if let Err(err) = daemon::detach() {
+            fail("Cannot daemonize", &err);
+        }
+    }
+    if let Some(pid_file) = &mut pid_file {
+        if let Err(err) = pid_file.write_pid() {
+            fail("Cannot write the PID file", &err);
+        }
+    }
+    pid_file
+}
+
+// The multi-threaded runtime, sized by [runtime]
+fn build_runtime(config: &RuntimeConfig) -> io::Result<Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all().max_blocking_threads(config.max_blocking_threads);
+    if config.worker_threads > 0 {
+        builder.worker_threads(config.worker_threads);
+    }
+    builder.build()
+}
+
+async fn serve(cli: Cli, config: Config) {
+    if let Err(err) = logging::init(cli.log_filter.as_deref(), &config.logging, &config.otel) {
+        eprintln!("Config error: {}", err);
+        std::process::exit(1);
+    }
+
+    let state = match AppState::from_config(&config) {
+        Ok(state) => Arc::new(state),
+        Err(err) => {
+            tracing::error!("Config error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(state.clone(), cli.clone(), config.clone()));
+    tokio::spawn(rate_limit::prune(state.clone()));
+
+    // Watches until the server stops
+    let _watcher = match config.watch.enabled {
+        true => match watcher::watch(state.clone()) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                tracing::error!("Cannot watch the served directory: {}", err);
+                std::process::exit(1);
+            }
+        },
+        false => None,
+    };
+
+    // Form uploads let clients POST files to directories; they stream to
+    // disk and enforce their own size limits
+    let allow = match state.form_uploads.is_some() {
+        true => FILE_AND_UPLOAD_METHODS,
+        false => FILE_METHODS,
+    };
+    let mut files = get(handle_get)
+        .options(describe(allow))
+        .fallback(error::method_not_allowed(allow));
+    if state.form_uploads.is_some() {
+        files = files.post(form_upload::upload).layer(DefaultBodyLimit::disable());
+    }
+
     // Create router with simpler middleware stack
-    let app = Router::new()
-        .route("/*path", get(handle_get))
-        // Add just timeout middleware
-        .layer(TimeoutLayer::new(Duration::from_secs(30)));
+    let mut app = Router::new()
+        .route("/*path", files);
+
+    if state.form_uploads.is_some() {
+        app = app.route("/", post(form_upload::upload)
+            .options(describe(UPLOAD_METHODS))
+            .fallback(error::method_not_allowed(UPLOAD_METHODS))
+            .layer(DefaultBodyLimit::disable()));
+    }
+
+    if state.tus.is_some() {
+        app = app.merge(tus::routes());
+    }
+
+    if state.events.is_some() {
+        app = app.merge(events::routes());
+    }
+
+    if state.search.is_some() {
+        app = app.merge(search::routes());
+    }
+
+    if state.admin.is_some() {
+        app = app.merge(admin::routes(state.clone()));
+    }
+
+    let mut app = app
+        // Bound the total time a request may take, if configured
+        .layer(middleware::from_fn_with_state(state.clone(), timeouts::deadline))
+        // Keep single clients within their request budgets
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit::limit_rate))
+        // Shed load beyond the configured concurrency limits
+        .layer(middleware::from_fn_with_state(state.clone(), limits::limit_concurrency))
+        // Refuse requests flooding us with header fields
+        .layer(middleware::from_fn_with_state(state.clone(), limits::check_headers))
+        // Render error bodies in the format the client asked for
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        // Close connections that are old, busy or being drained
+        .layer(middleware::from_fn_with_state(state.clone(), limits::limit_connection_lifetime));
+
+    // Answer preflights and let browsers on other origins read responses,
+    // rejections and errors included
+    if let Some(cors) = state.cors.clone() {
+        app = app.layer(cors);
+    }
+
+    let mut app = app
+        // Harden responses against misuse by browsers
+        .layer(middleware::from_fn_with_state(state.clone(), security_headers::add))
+        // Warn about slow requests, saying where the time went
+        .layer(middleware::from_fn_with_state(state.clone(), timing::log_slow_requests))
+        // Log every request with its final status and size
+        .layer(middleware::from_fn_with_state(state.clone(), access_log::log_requests))
+        // Count and time requests for the metrics endpoint
+        .layer(middleware::from_fn_with_state(state.clone(), metrics::record_requests))
+        // Run each request in its own tracing span
+        .layer(middleware::from_fn(logging::trace_requests))
+        // Find the client behind any trusted proxies
+        .layer(middleware::from_fn_with_state(state.clone(), proxies::resolve_client))
+        // Tag each request with an ID, available to all the layers above
+        .layer(middleware::from_fn_with_state(state.clone(), request_id::assign))
+        .with_state(state.clone());
+
+    // Probes skip the limits and logging applied to file requests
+    if config.health.enabled {
+        app = app.merge(health::routes(state.clone()));
+    }
+
+    // Metrics go on their own address if one is configured, else next to
+    // the files
+    if let Some(metrics) = &state.metrics {
+        match config.metrics.bind {
+            Some(addr) => serve_metrics(addr, metrics::routes(metrics.clone())),
+            None => app = app.merge(metrics::routes(metrics.clone())),
+        }
+    }
+
+    // Outermost, so idle timeouts wait for every response to be sent
+    let app = app.layer(middleware::from_fn(listener::track_requests));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    println!("File server running on http://{}", addr);
+    let listener = match Listener::bind(&addr, &config, &state) {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("Server error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("File server running on http://{}", listener.local_addr());
 
-    // Build server with graceful shutdown
-    let server = axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal());
+    // Build server with graceful shutdown. Timeouts apply per phase rather
+    // than per request, so long transfers run as long as data keeps moving
+    let mut builder = axum::Server::builder(listener)
+        .http1_max_buf_size(config.limits.max_header_bytes);
+    if let Some(header_read) = config.timeouts.header_read() {
+        builder = builder.http1_header_read_timeout(header_read);
+    }
+    // On shutdown, stop accepting and let requests in flight finish, for
+    // at most the grace period
+    let (stopping, stopped) = tokio::sync::oneshot::channel();
+    let server = builder
+        .serve(app.into_make_service_with_connect_info::<ConnectionInfo>())
+        .with_graceful_shutdown(async {
+            shutdown_signal().await;
+            let _ = stopping.send(());
+        });
+    let grace = config.shutdown.grace();
+    let grace_over = async move {
+        if stopped.await.is_err() {
+            return std::future::pending().await;
+        }
+        match grace {
+            Some(grace) => tokio::time::sleep(grace).await,
+            None => std::future::pending().await,
+        }
+    };
 
     // Start server
-    if let Err(err) = server.await {
-        eprintln!("Server error: {}", err);
+    let result = tokio::select! {
+        result = server => result,
+        () = grace_over => {
+            tracing::warn!("Shutdown grace period over, dropping remaining connections");
+            Ok(())
+        }
+    };
+    // Write out access log lines still queued
+    let flushed = state.clone();
+    let _ = tokio::task::spawn_blocking(move || {
+        if let Some(access_log) = &flushed.access_log {
+            access_log.flush();
+        }
+    }).await;
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+    if let Err(err) = result {
+        tracing::error!("Server error: {}", err);
         std::process::exit(1);
     }
 }
 
-async fn handle_get(Path(path): Path<String>) -> Result<Response, AppError> {
+// Methods served below the root; POST uploads files into directories when
+// form uploads are on
+const FILE_METHODS: &str = "GET, HEAD, OPTIONS";
+const UPLOAD_METHODS: &str = "POST, OPTIONS";
+const FILE_AND_UPLOAD_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+
+// OPTIONS handler listing the `allow` methods
+fn describe(allow: &'static str) -> impl Fn() -> Ready<[(HeaderName, &'static str); 1]> + Clone {
+    move || ready([(header::ALLOW, allow)])
+}
+
+async fn handle_get(
+    State(state): State<Arc<AppState>>,
+    method: Method,
+    Path(path): Path<String>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     // Sanitize and validate path
-    let path = PathBuf::from(path);
-    
-    // Prevent directory traversal attacks
-    if path.components().any(|c| c.as_os_str() == "..") {
-        return Err(AppError::InvalidPath("Path contains '..' which is not allowed".into()));
-    }
+    let paths = state.paths.get();
+    let path = paths.resolve(&path).await?;
 
-    // Check if file exists and is actually a file
-    let metadata = fs::metadata(&path).await
-        .map_err(|_| AppError::NotFound(path.display().to_string()))?;
+    // Check if file exists and is actually a file, or a directory the
+    // client wants as an archive or its metadata
+    let stat = state.stats.stat(&path).await?;
+    let variant = Variant::requested(&state, &path, query.as_deref(), &headers, &stat)?;
+    // Held until the response is sent, hashing for headers included
+    let handle = match stat.is_file {
+        true => state.open_files.reserve().await,
+        false => None,
+    };
 
-    if !metadata.is_file() {
-        return Err(AppError::InvalidPath(format!("{} is not a file", path.display())));
+    // Conditional requests are answered alike whichever variant they ask
+    // for. An archive covers a whole tree, which no validator describes
+    let etag = match stat.is_file {
+        true => state.etags.tag(&state.digests, &paths, &path, &stat).await?,
+        false => None,
+    };
+    let modified = match variant {
+        Variant::Archive(_) => None,
+        _ => stat.modified,
+    };
+    let resource = preconditions::Resource { etag: etag.as_deref(), modified };
+    let evaluation = preconditions::check_preconditions(resource, &headers, &method)?;
+
+    let mut response = match (evaluation, variant) {
+        (preconditions::Evaluation::NotModified, _) => StatusCode::NOT_MODIFIED.into_response(),
+        (_, Variant::Info) => file_info::response(&state, &path, etag.as_deref()).await?,
+        (_, Variant::Archive(format)) => {
+            let body = match (&method, &state.archives) {
+                (&Method::HEAD, _) | (_, None) => boxed(Empty::new()),
+                (_, Some(archiver)) => archiver.body(format, paths.clone(), path.clone()),
+            };
+            Response::builder()
+                .header(header::CONTENT_TYPE, format.mime_type())
+                .header(header::CONTENT_DISPOSITION, format.disposition(&path))
+                .body(body)
+                .unwrap()
+        }
+        // The file's checksum instead of its content
+        (_, Variant::Checksum(algorithm)) => {
+            let digest = state.digests.compute(&paths, &path, &stat, etag.as_deref(), algorithm).await?;
+            let Some(digest) = digest else {
+                return Err(AppError::Rejected(StatusCode::PAYLOAD_TOO_LARGE, format!(
+                    "{} is too large to checksum on demand", path.display(),
+                )));
+            };
+            digest::checksum_response(&path, &digest)
+        }
+        // A preview instead of the image itself
+        (_, Variant::Thumbnail(size)) => {
+            let Some(thumbnails) = &state.thumbnails else {
+                return Err(AppError::NotFound("thumbnails".into()));
+            };
+            let file = paths.open(&path).await?;
+            let thumbnail = thumbnails.get(file, &path, &stat, size).await?;
+            thumbnails::response(&state, &thumbnail).await?
+                .map(|body| limits::guarded(body, handle))
+        }
+        // HEAD is answered from the metadata alone, without opening the file
+        (_, Variant::File) if method == Method::HEAD => {
+            file_response(&state, &paths, &path, &stat, etag.as_deref(), &headers, boxed(Empty::new())).await?
+        }
+        // The stat the validators came from describes the body too, so a
+        // GET costs one stat however many headers it gets, and none more
+        // when the content is cached
+        (_, Variant::File) => {
+            let body = match state.files.cached(&path, &stat) {
+                Some(body) => body,
+                None => {
+                    let file = paths.open(&path).await?;
+                    limits::guarded(state.files.body(&path, file, &stat).await?, handle)
+                }
+            };
+            file_response(&state, &paths, &path, &stat, etag.as_deref(), &headers, body).await?
+        }
+    };
+
+    // Validators go on 304s too, as do the caching headers of the file and
+    // its previews, and for the file itself the configured headers, last
+    // so they can replace Cache-Control
+    let response_headers = response.headers_mut();
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(&etag).ok()) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    if let Some(modified) = modified {
+        let modified = HeaderValue::from_str(&httpdate::fmt_http_date(modified)).unwrap();
+        response_headers.insert(header::LAST_MODIFIED, modified);
+    }
+    if matches!(variant, Variant::File | Variant::Thumbnail(_)) {
+        if let Some(cache_control) = state.cache_control.get().header_for(&path) {
+            response_headers.insert(header::CACHE_CONTROL, cache_control);
+        }
+    }
+    if variant == Variant::File {
+        for (name, value) in state.response_headers.get().headers_for(&path) {
+            response_headers.insert(name, value.clone());
+        }
     }
+    Ok(response)
+}
 
-    // Open the file
-    let file = fs::File::open(&path)
-        .await
-        .map_err(AppError::IoError)?;
-    
-    let metadata = file.metadata()
-        .await
-        .map_err(AppError::IoError)?;
+// What a GET or HEAD asks for: a file, or something about a file or
+// directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    File,
+    // Metadata as JSON, with ?stat=json
+    Info,
+    Archive(archive::Format),
+    Checksum(digest::Algorithm),
+    Thumbnail(u32),
+}
 
-    // Create a buffered reader with a reasonable buffer size (64KB)
-    let stream = ReaderStream::new(BufReader::with_capacity(65536, file));
-    let body = StreamBody::new(stream);
+impl Variant {
+    fn requested(
+        state: &AppState,
+        path: &std::path::Path,
+        query: Option<&str>,
+        headers: &HeaderMap,
+        stat: &Stat,
+    ) -> Result<Variant, AppError> {
+        if file_info::requested(query) {
+            return Ok(Variant::Info);
+        }
+        if let (true, Some(_)) = (stat.is_dir, &state.archives) {
+            if let Some(format) = archive::Format::requested(query, headers) {
+                return Ok(Variant::Archive(format));
+            }
+        }
+        if !stat.is_file {
+            return Err(AppError::InvalidPath(format!("{} is not a file", path.display())));
+        }
+        if let Some(algorithm) = digest::Algorithm::requested(query)? {
+            return Ok(Variant::Checksum(algorithm));
+        }
+        if let Some(thumbnails) = &state.thumbnails {
+            if let Some(size) = thumbnails.requested_size(query)? {
+                return Ok(Variant::Thumbnail(size));
+            }
+        }
+        Ok(Variant::File)
+    }
+}
 
+// Response for a file with the headers GET and HEAD have in common
+async fn file_response(
+    state: &AppState,
+    paths: &PathResolver,
+    path: &std::path::Path,
+    stat: &Stat,
+    etag: Option<&str>,
+    headers: &HeaderMap,
+    body: BoxBody,
+) -> Result<Response, AppError> {
     // Try to guess the MIME type
-    let mime_type = mime_guess::from_path(&path)
-        .first_or_octet_stream()
-        .to_string();
+    let mime_types = state.mime_types.get();
+    let mime_type = mime_types.content_type(path);
 
-    // Build response with proper headers
-    Ok(Response::builder()
+    let mut builder = Response::builder()
         .status(StatusCode::OK)
+        .header(header::CONTENT_LENGTH, stat.len);
+
+    if mime_types.is_attachment(path, &mime_type) {
+        builder = builder.header(header::CONTENT_DISPOSITION, mime_types::attachment_disposition(path));
+    }
+
+    // RFC 3230 instance digest, when the client asks for one
+    if let Some(algorithm) = digest::Algorithm::from_want_digest(headers) {
+        if let Some(value) = state.digests.compute(paths, path, stat, etag, algorithm).await? {
+            builder = builder.header(digest::DIGEST, algorithm.digest_header(&value));
+        }
+    }
+
+    Ok(builder
         .header(header::CONTENT_TYPE, mime_type)
-        .header(header::CONTENT_LENGTH, metadata.len())
         .body(body)
         .unwrap()
         .into_response())
 }
 
+// Report problems with the configuration and return the exit code
+async fn check_config(cli: &Cli, config: &Config) -> i32 {
+    if let Err(err) = logging::filter(cli.log_filter.as_deref(), &config.logging) {
+        eprintln!("Config error: {}", err);
+        return 1;
+    }
+    let state = match AppState::from_config(config) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("Config error: {}", err);
+            return 1;
+        }
+    };
+
+    let mut dirs = vec![std::path::Path::new(".")];
+    if let Some(tus) = &state.tus {
+        dirs.push(tus.staging_dir());
+    }
+    if let Some(staging_dir) = &state.uploads.staging_dir {
+        dirs.push(staging_dir);
+    }
+    if let Some(thumbnails) = &state.thumbnails {
+        dirs.push(thumbnails.cache_dir());
+    }
+    for dir in dirs {
+        if let Err(err) = fs::read_dir(dir).await {
+            eprintln!("Cannot read {}: {}", dir.display(), err);
+            return 2;
+        }
+    }
+
+    println!("Configuration OK");
+    0
+}
+
+// Reload the config file on SIGHUP. In-flight requests keep the settings
+// they started with; `started` is the configuration the server runs with
+#[cfg(unix)]
+async fn reload_on_hangup(state: Arc<AppState>, cli: Cli, started: Config) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            tracing::warn!("Cannot listen for SIGHUP, reloading is disabled: {}", err);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        let Some(path) = cli.config.as_deref() else {
+            tracing::warn!("SIGHUP received, but there is no config file to reload");
+            continue;
+        };
+        let mut config = match Config::load(Some(path)) {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::error!("Config reload failed, keeping the current settings: {}", err);
+                continue;
+            }
+        };
+        if let Some(format) = cli.log_format {
+            config.logging.format = format;
+        }
+
+        let reloaded = logging::filter(cli.log_filter.as_deref(), &config.logging)
+            .and_then(|filter| state.reload(&config).map(|()| filter));
+        match reloaded {
+            Ok(filter) => logging::set_filter(filter),
+            Err(err) => {
+                tracing::error!("Config reload failed, keeping the current settings: {}", err);
+                continue;
+            }
+        }
+
+        tracing::info!("Configuration reloaded from {}", path.display());
+        let pending = config.restart_required(&started);
+        if !pending.is_empty() {
+            tracing::warn!("Changes to [{}] take effect after a restart", pending.join("], ["));
+        }
+    }
+}
+
+// Serve the metrics router on a separate address, in the background
+fn serve_metrics(addr: SocketAddr, routes: Router) {
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(routes.into_make_service()),
+        Err(err) => {
+            tracing::error!("Metrics server error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    tracing::info!("Metrics available on http://{}/metrics", addr);
+    tokio::spawn(async move {
+        if let Err(err) = server.await {
+            tracing::error!("Metrics server error: {}", err);
+        }
+    });
+}
+
 // Graceful shutdown handler
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -119,7 +695,10 @@ async fn shutdown_signal() {
             .await;
     };
 
-    #[cfg(not(unix))]
+    #[cfg(windows)]
+    let terminate = service::stopped();
+
+    #[cfg(not(any(unix, windows)))]
     let terminate = std::future::pending::<()>();
 
     tokio::select! {
@@ -127,5 +706,5 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 
-    println!("shutdown signal received, starting graceful shutdown");
+    tracing::info!("shutdown signal received, starting graceful shutdown");
 }
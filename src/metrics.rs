@@ -0,0 +1,197 @@
+use axum::{
+    extract::State,
+    http::{header, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use crate::state::AppState;
+
+// Upper bounds of the request duration histogram buckets, in seconds
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+// Counters exposed in the Prometheus text format
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // Requests by method and status
+    requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+    // Cumulative counts per bucket of DURATION_BUCKETS
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_micros: AtomicU64,
+    duration_count: AtomicU64,
+    received_bytes: AtomicU64,
+    sent_bytes: AtomicU64,
+    connections: AtomicI64,
+}
+
+impl Metrics {
+    pub fn connection_opened(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn connection_closed(&self) {
+        self.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.received_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.sent_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn request_done(&self, method: &'static str, status: u16, started: Instant) {
+        *self.requests.lock().unwrap().entry((method, status)).or_default() += 1;
+
+        let elapsed = started.elapsed();
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            if elapsed.as_secs_f64() <= bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.duration_micros.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        self.duration_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests answered, by method and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(out, "http_requests_total{{method=\"{}\",status=\"{}\"}} {}", method, status, count);
+        }
+
+        out.push_str("# HELP http_request_duration_seconds Time until the response head was ready.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for (bucket, bound) in self.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            let _ = writeln!(
+                out, "http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, bucket.load(Ordering::Relaxed),
+            );
+        }
+        let count = self.duration_count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "http_request_duration_seconds_bucket{{le=\"+Inf\"}} {}", count);
+        let _ = writeln!(
+            out, "http_request_duration_seconds_sum {}",
+            self.duration_micros.load(Ordering::Relaxed) as f64 / 1e6,
+        );
+        let _ = writeln!(out, "http_request_duration_seconds_count {}", count);
+
+        let counters = [
+            ("http_received_bytes_total", "counter", "Bytes received from clients, headers included.", self.received_bytes.load(Ordering::Relaxed) as i64),
+            ("http_sent_bytes_total", "counter", "Bytes sent to clients, headers included.", self.sent_bytes.load(Ordering::Relaxed) as i64),
+            ("http_connections_active", "gauge", "Client connections currently open.", self.connections.load(Ordering::Relaxed)),
+        ];
+        for (name, kind, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        }
+
+        out
+    }
+}
+
+// Middleware counting requests and timing them
+pub async fn record_requests<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(metrics) = &state.metrics else {
+        return next.run(req).await;
+    };
+
+    let method = method_label(req.method());
+    let started = Instant::now();
+    let response = next.run(req).await;
+    metrics.request_done(method, response.status().as_u16(), started);
+    response
+}
+
+pub async fn render(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        metrics.render(),
+    )
+}
+
+// Router serving the metrics, with the metrics as its state
+pub fn routes(metrics: Arc<Metrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(render))
+        .with_state(metrics)
+}
+
+// Method as a label, folding unknown methods together so a client cannot
+// create unbounded label values
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, MetricsConfig};
+    use axum::{body::Body, http::StatusCode, middleware};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn requests_are_counted_by_method_and_status() {
+        let config = Config { metrics: MetricsConfig { enabled: true, bind: None }, ..Config::default() };
+        let state = Arc::new(AppState::from_config(&config).unwrap());
+        let app = Router::new()
+            .route("/a.txt", get(|| async { "a" }))
+            .layer(middleware::from_fn_with_state(state.clone(), record_requests));
+        for (method, uri) in [("GET", "/a.txt"), ("GET", "/a.txt"), ("BREW", "/a.txt"), ("GET", "/missing")] {
+            let req = Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let text = state.metrics.as_ref().unwrap().render();
+        assert!(text.contains("http_requests_total{method=\"GET\",status=\"200\"} 2\n"), "{}", text);
+        assert!(text.contains("http_requests_total{method=\"GET\",status=\"404\"} 1\n"), "{}", text);
+        assert!(text.contains("http_requests_total{method=\"other\",status=\"405\"} 1\n"), "{}", text);
+        assert!(text.contains("http_request_duration_seconds_bucket{le=\"+Inf\"} 4\n"), "{}", text);
+        assert!(text.contains("http_request_duration_seconds_count 4\n"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn traffic_and_connections_are_reported() {
+        let metrics = Arc::new(Metrics::default());
+        metrics.connection_opened();
+        metrics.connection_opened();
+        metrics.connection_closed();
+        metrics.received(100);
+        metrics.sent(250);
+
+        let response = routes(metrics).oneshot(Request::get("/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain; version=0.0.4"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("\nhttp_received_bytes_total 100\n"), "{}", text);
+        assert!(text.contains("\nhttp_sent_bytes_total 250\n"), "{}", text);
+        assert!(text.contains("\nhttp_connections_active 1\n"), "{}", text);
+    }
+}
@@ -0,0 +1,235 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use axum::http::HeaderValue;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::config::{ConfigError, MimeConfig};
+
+// Extension to MIME type lookup, with configured overrides taking
+// precedence over mime_guess
+#[derive(Debug, Default)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+    charset: String,
+    charsets: HashMap<String, String>,
+    attachment_types: Vec<String>,
+    attachment_extensions: Vec<String>,
+}
+
+impl MimeTypes {
+    pub fn from_config(config: &MimeConfig) -> Result<MimeTypes, ConfigError> {
+        let mut overrides = HashMap::new();
+
+        // Lines in mime.types format: "type/subtype ext1 ext2 ..."
+        if let Some(path) = &config.types_file {
+            let text = fs::read_to_string(path)
+                .map_err(|err| ConfigError::Io(path.clone(), err))?;
+
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap_or("");
+                let mut fields = line.split_whitespace();
+                let Some(mime) = fields.next() else {
+                    continue;
+                };
+                for ext in fields {
+                    overrides.insert(ext.to_ascii_lowercase(), mime.to_string());
+                }
+            }
+        }
+
+        // Inline entries win over the types file
+        for (ext, mime) in &config.types {
+            overrides.insert(ext.trim_start_matches('.').to_ascii_lowercase(), mime.clone());
+        }
+
+        if let Some(mime) = overrides.values().find(|mime| HeaderValue::from_str(mime).is_err()) {
+            return Err(ConfigError::Invalid(format!("invalid MIME type {:?}", mime)));
+        }
+
+        let charsets: HashMap<String, String> = config.charsets.iter()
+            .map(|(ext, charset)| (ext.trim_start_matches('.').to_ascii_lowercase(), charset.clone()))
+            .collect();
+
+        let bad_charset = charsets.values()
+            .chain([&config.charset])
+            .find(|charset| !charset.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)));
+        if let Some(charset) = bad_charset {
+            return Err(ConfigError::Invalid(format!("invalid charset {:?}", charset)));
+        }
+
+        Ok(MimeTypes {
+            overrides,
+            charset: config.charset.clone(),
+            charsets,
+            attachment_types: config.attachment_types.iter()
+                .map(|mime| mime.to_ascii_lowercase())
+                .collect(),
+            attachment_extensions: config.attachment_extensions.iter()
+                .map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())
+                .collect(),
+        })
+    }
+
+    // Content-Type for a file, with a charset parameter added to text types
+    pub fn content_type(&self, path: &Path) -> String {
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        let mime = match ext.as_ref().and_then(|ext| self.overrides.get(ext)) {
+            Some(mime) => mime.clone(),
+            None => mime_guess::from_path(path)
+                .first_or_octet_stream()
+                .to_string(),
+        };
+
+        // Leave non-text types and types already carrying parameters alone
+        if !mime.starts_with("text/") || mime.contains(';') {
+            return mime;
+        }
+
+        let charset = ext.as_ref()
+            .and_then(|ext| self.charsets.get(ext))
+            .unwrap_or(&self.charset);
+
+        if charset.is_empty() {
+            mime
+        } else {
+            format!("{}; charset={}", mime, charset)
+        }
+    }
+
+    // Whether a file must be downloaded rather than rendered by the browser
+    pub fn is_attachment(&self, path: &Path, content_type: &str) -> bool {
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        if ext.is_some_and(|ext| self.attachment_extensions.contains(&ext)) {
+            return true;
+        }
+
+        let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        let top_level = mime.split('/').next().unwrap_or("");
+        self.attachment_types.iter().any(|pattern| match pattern.strip_suffix("/*") {
+            Some(prefix) => prefix == top_level,
+            None => *pattern == mime,
+        })
+    }
+}
+
+// Content-Disposition value forcing a download under the file's own name
+pub fn attachment_disposition(path: &Path) -> String {
+    let name = path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    // Plain ASCII fallback plus the RFC 6266 encoded form for other names
+    let fallback: String = name.chars()
+        .map(|c| if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+    let encoded = utf8_percent_encode(&name, NON_ALPHANUMERIC);
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn mime_types(config: MimeConfig) -> MimeTypes {
+        MimeTypes::from_config(&config).unwrap()
+    }
+
+    fn bare() -> MimeConfig {
+        MimeConfig { charset: String::new(), ..MimeConfig::default() }
+    }
+
+    #[test]
+    fn known_extensions_come_from_mime_guess() {
+        let types = mime_types(bare());
+        assert_eq!(types.content_type(Path::new("a/video.MP4")), "video/mp4");
+        assert_eq!(types.content_type(Path::new("page.html")), "text/html");
+        // Anything else is served as opaque bytes
+        assert_eq!(types.content_type(Path::new("model.gcode")), "application/octet-stream");
+        assert_eq!(types.content_type(Path::new("Makefile")), "application/octet-stream");
+    }
+
+    #[test]
+    fn inline_types_win_over_the_types_file() {
+        let dir = scratch_dir("mime-types-file");
+        std::fs::write(dir.join("mime.types"), concat!(
+            "# comment\n",
+            "text/x-gcode gcode GCO\n",
+            "video/x-matroska mkv mk3d # trailing comment\n",
+        )).unwrap();
+        let types = mime_types(MimeConfig {
+            types_file: Some(dir.join("mime.types")),
+            types: [(".mkv".to_string(), "video/webm".to_string())].into(),
+            ..bare()
+        });
+
+        assert_eq!(types.content_type(Path::new("part.gco")), "text/x-gcode");
+        assert_eq!(types.content_type(Path::new("film.MKV")), "video/webm");
+        assert_eq!(types.content_type(Path::new("film.mk3d")), "video/x-matroska");
+    }
+
+    #[test]
+    fn invalid_types_are_rejected() {
+        let config = MimeConfig { types: [("x".to_string(), "bad\ntype".to_string())].into(), ..bare() };
+        assert!(matches!(MimeTypes::from_config(&config), Err(ConfigError::Invalid(_))));
+        let missing = MimeConfig { types_file: Some("missing/mime.types".into()), ..bare() };
+        assert!(matches!(MimeTypes::from_config(&missing), Err(ConfigError::Io(..))));
+    }
+
+    #[test]
+    fn text_types_get_a_charset() {
+        let types = mime_types(MimeConfig {
+            charsets: [(".txt".to_string(), "iso-8859-1".to_string())].into(),
+            types: [("csv".to_string(), "text/csv; header=present".to_string())].into(),
+            ..MimeConfig::default()
+        });
+        assert_eq!(types.content_type(Path::new("page.html")), "text/html; charset=utf-8");
+        assert_eq!(types.content_type(Path::new("old.TXT")), "text/plain; charset=iso-8859-1");
+        assert_eq!(types.content_type(Path::new("data.csv")), "text/csv; header=present");
+        assert_eq!(types.content_type(Path::new("image.png")), "image/png");
+        assert_eq!(mime_types(bare()).content_type(Path::new("page.html")), "text/html");
+    }
+
+    #[test]
+    fn charsets_must_be_tokens() {
+        let config = MimeConfig { charset: "utf-8; x=y".into(), ..MimeConfig::default() };
+        assert!(matches!(MimeTypes::from_config(&config), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn attachments_are_chosen_by_type_or_extension() {
+        let types = mime_types(MimeConfig {
+            attachment_types: vec!["text/html".into(), "Image/*".into()],
+            attachment_extensions: vec![".MD".into()],
+            ..MimeConfig::default()
+        });
+        let attachment = |name: &str| {
+            let path = Path::new(name);
+            types.is_attachment(path, &types.content_type(path))
+        };
+
+        assert!(attachment("page.html"));
+        assert!(attachment("photo.jpg"));
+        assert!(attachment("README.md"));
+        assert!(!attachment("notes.txt"));
+        assert!(!attachment("page.xhtml"));
+    }
+
+    #[test]
+    fn dispositions_keep_the_file_name() {
+        assert_eq!(
+            attachment_disposition(Path::new("dir/a \"b\".txt")),
+            "attachment; filename=\"a _b_.txt\"; filename*=UTF-8''a%20%22b%22%2Etxt",
+        );
+        assert_eq!(
+            attachment_disposition(Path::new("résumé.pdf")),
+            "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%2Epdf",
+        );
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    ffi::OsStr,
+    path::{Component, Path, PathBuf},
+};
+#[cfg(target_os = "linux")]
+use std::{io, sync::Arc};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use tokio::fs;
+use unicode_normalization::UnicodeNormalization as _;
+
+use crate::{
+    config::{ConfigError, DenyStatus, NamePolicy, PathConfig, SymlinkPolicy, UnicodeNormalization},
+    error::AppError,
+    timing,
+};
+
+// Maps request paths onto the served directory and enforces path policies
+#[derive(Debug)]
+pub struct PathResolver {
+    // Canonical form of the served directory, for containment checks
+    root: PathBuf,
+    // The served directory itself, which opens resolve beneath
+    #[cfg(target_os = "linux")]
+    root_dir: Arc<std::fs::File>,
+    symlinks: SymlinkPolicy,
+    hide_hidden: bool,
+    deny: GlobSet,
+    allow: Option<GlobSet>,
+    drop_folders: GlobSet,
+    deny_status: DenyStatus,
+    normalization: UnicodeNormalization,
+    case_insensitive: bool,
+    new_names: NamePolicy,
+}
+
+impl PathResolver {
+    pub fn from_config(config: &PathConfig) -> Result<PathResolver, ConfigError> {
+        let root = std::fs::canonicalize(".")
+            .map_err(|err| ConfigError::Io(".".into(), err))?;
+
+        // The policy must not depend on the case clients use when paths are
+        // looked up ignoring it
+        let globs = |patterns: &[String]| build_globs_matching(patterns, config.case_insensitive);
+        let allow = if config.allow.is_empty() {
+            None
+        } else {
+            Some(globs(&config.allow)?)
+        };
+
+        Ok(PathResolver {
+            root,
+            #[cfg(target_os = "linux")]
+            root_dir: Arc::new(std::fs::File::open(".")
+                .map_err(|err| ConfigError::Io(".".into(), err))?),
+            symlinks: config.symlinks,
+            hide_hidden: config.hide_hidden,
+            deny: globs(&config.deny)?,
+            allow,
+            drop_folders: globs(&config.drop_folders)?,
+            deny_status: config.deny_status,
+            normalization: config.unicode_normalization,
+            case_insensitive: config.case_insensitive,
+            new_names: config.new_names,
+        })
+    }
+
+    // Validate a request path and return it relative to the served directory
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf, AppError> {
+        let path = self.resolve_upload_dir(path).await?;
+        if self.in_drop_folder(&path) {
+            return Err(AppError::PermissionDenied(path.display().to_string()));
+        }
+        Ok(path)
+    }
+
+    // Like `resolve`, for the directory uploads go into, which may be a drop
+    // folder
+    pub async fn resolve_upload_dir(&self, path: &str) -> Result<PathBuf, AppError> {
+        let path = self.lookup(&self.parse(path)?).await;
+
+        self.check_visible(&path).await?;
+        self.check_symlinks(&path).await?;
+
+        Ok(path)
+    }
+
+    // Like `resolve`, for the target of a write, which need not exist yet
+    // but whose parent collection must
+    pub async fn resolve_new(&self, path: &str) -> Result<PathBuf, AppError> {
+        let path = self.parse(path)?;
+        if let Some(name) = path.components().find_map(|component| match component {
+            Component::Normal(name) if !self.is_acceptable_name(name) => Some(name),
+            _ => None,
+        }) {
+            return Err(AppError::InvalidPath(format!(
+                "{:?} is not an accepted file name", name.to_string_lossy(),
+            )));
+        }
+        let path = self.lookup(&path).await;
+
+        let (Some(parent), Some(_)) = (path.parent(), path.file_name()) else {
+            return Err(AppError::InvalidPath("Path must name a file".into()));
+        };
+
+        self.check_visible(&path).await?;
+        if !parent.as_os_str().is_empty() {
+            self.check_symlinks(parent).await.map_err(|err| match err {
+                AppError::NotFound(_) => AppError::Conflict(parent.display().to_string()),
+                err => err,
+            })?;
+        }
+
+        Ok(path)
+    }
+
+    fn parse(&self, path: &str) -> Result<PathBuf, AppError> {
+        let path = PathBuf::from(self.normalize(path));
+
+        // Prevent directory traversal attacks and absolute paths
+        for component in path.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => {}
+                Component::ParentDir =>
+                    return Err(AppError::InvalidPath("Path contains '..' which is not allowed".into())),
+                Component::RootDir | Component::Prefix(_) =>
+                    return Err(AppError::InvalidPath("Path must be relative".into())),
+            }
+        }
+
+        Ok(path)
+    }
+
+    // Open a resolved file for reading. Symlinks may have been swapped since
+    // the path was checked, so on Linux the open enforces the policy again
+    pub async fn open(&self, path: &Path) -> Result<fs::File, AppError> {
+        #[cfg(target_os = "linux")]
+        if self.symlinks != SymlinkPolicy::Follow {
+            let (root_dir, root, symlinks) = (self.root_dir.clone(), self.root.clone(), self.symlinks);
+            let target = path.to_path_buf();
+            let opened = timing::fs_wait(tokio::task::spawn_blocking(move || {
+                open_beneath(&root_dir, &root, &target, symlinks)
+            })).await;
+            return match opened {
+                Ok(Ok(file)) => Ok(fs::File::from_std(file)),
+                Ok(Err(err)) => Err(AppError::from_io(err, path)),
+                Err(err) => Err(AppError::Io(path.display().to_string(), io::Error::other(err))),
+            };
+        }
+        timing::fs_wait(fs::File::open(path)).await.map_err(|err| AppError::from_io(err, path))
+    }
+
+    // Whether writes may create a file or directory of this name
+    fn is_acceptable_name(&self, name: &OsStr) -> bool {
+        let Some(name) = name.to_str() else {
+            // Not representable on filesystems storing Unicode names
+            return self.new_names == NamePolicy::Any;
+        };
+        match self.new_names {
+            NamePolicy::Any => true,
+            NamePolicy::NoControl => !name.chars().any(char::is_control),
+            NamePolicy::Portable => !name.chars().any(char::is_control) && is_portable(name),
+        }
+    }
+
+    // Whether clients may see the path, judged by name alone, for paths
+    // that may no longer exist
+    pub fn is_visible(&self, path: &Path) -> bool {
+        !(self.hide_hidden && is_dotfile(path)) && self.is_allowed(path) && !self.in_drop_folder(path)
+    }
+
+    // Whether the path is a drop folder or lies below one
+    fn in_drop_folder(&self, path: &Path) -> bool {
+        !self.drop_folders.is_empty()
+            && path.ancestors().any(|dir| self.drop_folders.is_match(glob_path(dir)))
+    }
+
+    // Apply the hidden-file policy and the deny/allow lists
+    async fn check_visible(&self, path: &Path) -> Result<(), AppError> {
+        if self.hide_hidden && is_hidden(path).await {
+            return Err(AppError::NotFound(path.display().to_string()));
+        }
+
+        if !self.is_allowed(path) {
+            return Err(match self.deny_status {
+                DenyStatus::NotFound => AppError::NotFound(path.display().to_string()),
+                DenyStatus::Forbidden => AppError::PermissionDenied(path.display().to_string()),
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn check_symlinks(&self, path: &Path) -> Result<(), AppError> {
+        match self.symlinks {
+            SymlinkPolicy::Follow => Ok(()),
+            SymlinkPolicy::Deny => self.reject_symlinks(path).await,
+            SymlinkPolicy::InsideRoot => self.check_inside_root(path).await,
+        }
+    }
+
+    fn normalize(&self, name: &str) -> String {
+        match self.normalization {
+            UnicodeNormalization::None => name.to_string(),
+            UnicodeNormalization::Nfc => name.nfc().collect(),
+            UnicodeNormalization::Nfd => name.nfd().collect(),
+        }
+    }
+
+    // Key under which two file names are considered the same
+    fn match_key(&self, name: &str) -> String {
+        let name = self.normalize(name);
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    // Map each component to the directory entry it names on disk, so a name
+    // stored in a different normalization form or case is still found
+    async fn lookup(&self, path: &Path) -> PathBuf {
+        if self.normalization == UnicodeNormalization::None && !self.case_insensitive {
+            return path.to_path_buf();
+        }
+
+        let mut resolved = PathBuf::new();
+        let mut components = path.components();
+        for component in components.by_ref() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+
+            let candidate = resolved.join(name);
+            if fs::symlink_metadata(&candidate).await.is_ok() {
+                resolved = candidate;
+                continue;
+            }
+
+            match self.find_entry(&resolved, name).await {
+                Some(entry) => resolved.push(entry),
+                None => {
+                    // Leave the rest as requested so the caller reports it missing
+                    resolved.push(name);
+                    break;
+                }
+            }
+        }
+        resolved.extend(components);
+        resolved
+    }
+
+    // Find the entry of `dir` matching `name`; a name matching several
+    // entries (e.g. "a.txt" and "A.txt") is ambiguous and resolves to none
+    async fn find_entry(&self, dir: &Path, name: &OsStr) -> Option<PathBuf> {
+        let wanted = self.match_key(name.to_str()?);
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+        let mut found = None;
+        let mut entries = fs::read_dir(dir).await.ok()?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let file_name = entry.file_name();
+            if file_name.to_str().is_some_and(|candidate| self.match_key(candidate) == wanted) {
+                if found.is_some() {
+                    return None;
+                }
+                found = Some(PathBuf::from(file_name));
+            }
+        }
+        found
+    }
+
+    // Check the path against the deny and allow lists
+    fn is_allowed(&self, path: &Path) -> bool {
+        let normalized = glob_path(path);
+        if self.deny.is_match(&normalized) {
+            return false;
+        }
+        self.allow.as_ref().is_none_or(|allow| allow.is_match(&normalized))
+    }
+
+    // Fail if the path or any of its parents is a symlink
+    async fn reject_symlinks(&self, path: &Path) -> Result<(), AppError> {
+        let mut current = PathBuf::new();
+        for component in path.components() {
+            current.push(component);
+            let metadata = fs::symlink_metadata(&current).await
+                .map_err(|err| AppError::from_io(err, path))?;
+            if metadata.file_type().is_symlink() {
+                return Err(AppError::PermissionDenied(path.display().to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // Fail if following symlinks takes the path outside the served directory
+    async fn check_inside_root(&self, path: &Path) -> Result<(), AppError> {
+        let target = fs::canonicalize(path).await
+            .map_err(|err| AppError::from_io(err, path))?;
+        if !target.starts_with(&self.root) {
+            return Err(AppError::PermissionDenied(path.display().to_string()));
+        }
+        Ok(())
+    }
+}
+
+// Open `path` relative to the served directory without leaving it, and
+// without following any symlink under the deny policy
+#[cfg(target_os = "linux")]
+fn open_beneath(
+    root_dir: &std::fs::File,
+    root: &Path,
+    path: &Path,
+    symlinks: SymlinkPolicy,
+) -> io::Result<std::fs::File> {
+    use std::os::fd::AsRawFd;
+
+    let escaped = || io::Error::new(io::ErrorKind::PermissionDenied, "path leaves the served directory");
+    let resolve = match symlinks {
+        SymlinkPolicy::Deny => libc::RESOLVE_BENEATH | libc::RESOLVE_NO_SYMLINKS,
+        _ => libc::RESOLVE_BENEATH,
+    };
+    let err = match openat2(root_dir, path, resolve) {
+        Ok(file) => return Ok(file),
+        Err(err) => err,
+    };
+    match (err.raw_os_error(), symlinks) {
+        (Some(libc::EXDEV | libc::ELOOP), SymlinkPolicy::Deny) => return Err(escaped()),
+        // RESOLVE_BENEATH also refuses absolute symlinks that point back
+        // inside; those are checked below like on kernels without openat2
+        (Some(libc::EXDEV | libc::ENOSYS | libc::EPERM), SymlinkPolicy::InsideRoot) => {}
+        // Kernels before 5.6, or sandboxes blocking the call; the checks
+        // made while resolving stand
+        (Some(libc::ENOSYS | libc::EPERM), _) => return std::fs::File::open(path),
+        _ => return Err(err),
+    }
+
+    // See where the opened file really is. Without /proc that cannot be
+    // told, so the file is refused rather than trusted
+    let file = std::fs::File::open(path)?;
+    let target = std::fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))
+        .map_err(|_| escaped())?;
+    if !target.starts_with(root) {
+        return Err(escaped());
+    }
+    Ok(file)
+}
+
+#[cfg(target_os = "linux")]
+fn openat2(dir: &std::fs::File, path: &Path, resolve: u64) -> io::Result<std::fs::File> {
+    use std::{
+        ffi::CString,
+        os::{fd::{AsRawFd, FromRawFd}, unix::ffi::OsStrExt},
+    };
+
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    // SAFETY: open_how is plain data, and zero is its "no options" value
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (libc::O_RDONLY | libc::O_CLOEXEC) as u64;
+    how.resolve = resolve;
+    // SAFETY: the pointers are valid for the duration of the call
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the call returned a new descriptor that nothing else owns
+    Ok(unsafe { std::fs::File::from_raw_fd(fd as i32) })
+}
+
+// Whether Windows can store a file of this name
+fn is_portable(name: &str) -> bool {
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7",
+        "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    // Reserved names stay reserved with any extension, e.g. "nul.txt"
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    !RESERVED.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+        && !name.ends_with(['.', ' '])
+        && !name.contains(['<', '>', ':', '"', '\\', '|', '?', '*'])
+}
+
+// The path as matched against configured globs: '/'-separated on every
+// platform, without leading or "." components
+pub fn glob_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn build_globs(patterns: &[String]) -> Result<GlobSet, ConfigError> {
+    build_globs_matching(patterns, false)
+}
+
+fn build_globs_matching(patterns: &[String], case_insensitive: bool) -> Result<GlobSet, ConfigError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(case_insensitive)
+            .build()
+            .map_err(|err| ConfigError::Invalid(format!("invalid glob {:?}: {}", pattern, err)))?;
+        builder.add(glob);
+    }
+    builder.build()
+        .map_err(|err| ConfigError::Invalid(format!("invalid glob set: {}", err)))
+}
+
+fn is_dotfile(path: &Path) -> bool {
+    path.components().any(|component| {
+        matches!(component, Component::Normal(name) if name.to_string_lossy().starts_with('.'))
+    })
+}
+
+// Whether any component of the path is a dotfile or a Windows hidden file
+async fn is_hidden(path: &Path) -> bool {
+    if is_dotfile(path) {
+        return true;
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+        let mut current = PathBuf::new();
+        for component in path.components() {
+            current.push(component);
+            if let Ok(metadata) = fs::symlink_metadata(&current).await {
+                if metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn resolver(config: PathConfig) -> PathResolver {
+        PathResolver::from_config(&config).unwrap()
+    }
+
+    // As on a case-insensitive filesystem, the name as requested exists
+    #[tokio::test]
+    async fn deny_globs_ignore_case_when_lookups_do() {
+        let dir = scratch_dir("paths-case");
+        std::fs::write(dir.join("SECRET.KEY"), "").unwrap();
+        let paths = resolver(PathConfig {
+            deny: vec!["**/*.key".into()],
+            case_insensitive: true,
+            ..PathConfig::default()
+        });
+
+        let resolved = paths.resolve("paths-case/SECRET.KEY").await;
+        assert!(matches!(resolved, Err(AppError::NotFound(_))));
+        assert!(!paths.is_visible(Path::new("paths-case/Secret.Key")));
+    }
+
+    #[tokio::test]
+    async fn deny_globs_keep_case_by_default() {
+        let dir = scratch_dir("paths-case-sensitive");
+        std::fs::write(dir.join("SECRET.KEY"), "").unwrap();
+        let paths = resolver(PathConfig { deny: vec!["**/*.key".into()], ..PathConfig::default() });
+
+        assert!(paths.resolve("paths-case-sensitive/SECRET.KEY").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn paths_cannot_leave_the_served_directory() {
+        scratch_dir("paths-traversal");
+        let paths = resolver(PathConfig::default());
+
+        for path in ["..", "../etc/passwd", "paths-traversal/../../etc/passwd", "/etc/passwd"] {
+            let resolved = paths.resolve(path).await;
+            assert!(matches!(resolved, Err(AppError::InvalidPath(_))), "{}: {:?}", path, resolved);
+            assert!(matches!(paths.resolve_new(path).await, Err(AppError::InvalidPath(_))), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn dotfiles_are_hidden_at_any_depth() {
+        let dir = scratch_dir("paths-hidden");
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join(".git/config"), "").unwrap();
+        std::fs::write(dir.join(".env"), "").unwrap();
+        let hiding = resolver(PathConfig { hide_hidden: true, ..PathConfig::default() });
+        let showing = resolver(PathConfig { hide_hidden: false, ..PathConfig::default() });
+
+        for path in ["paths-hidden/.env", "paths-hidden/.git/config"] {
+            assert!(matches!(hiding.resolve(path).await, Err(AppError::NotFound(_))), "{}", path);
+            assert!(!hiding.is_visible(Path::new(path)));
+            assert!(showing.resolve(path).await.is_ok(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn deny_and_allow_lists_apply() {
+        let dir = scratch_dir("paths-lists");
+        for name in ["public.txt", "private.txt", "notes.md"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let paths = resolver(PathConfig {
+            deny: vec!["**/private.*".into()],
+            allow: vec!["**/*.txt".into()],
+            ..PathConfig::default()
+        });
+
+        assert!(paths.resolve("paths-lists/public.txt").await.is_ok());
+        // Deny wins over allow
+        assert!(matches!(paths.resolve("paths-lists/private.txt").await, Err(AppError::NotFound(_))));
+        assert!(matches!(paths.resolve("paths-lists/notes.md").await, Err(AppError::NotFound(_))));
+
+        let forbidding = resolver(PathConfig {
+            deny: vec!["**/private.*".into()],
+            deny_status: DenyStatus::Forbidden,
+            ..PathConfig::default()
+        });
+        let resolved = forbidding.resolve("paths-lists/private.txt").await;
+        assert!(matches!(resolved, Err(AppError::PermissionDenied(_))));
+    }
+
+    #[tokio::test]
+    async fn drop_folders_take_uploads_but_are_not_read() {
+        let dir = scratch_dir("paths-drop");
+        std::fs::create_dir_all(dir.join("inbox")).unwrap();
+        std::fs::write(dir.join("inbox/upload.txt"), "").unwrap();
+        let paths = resolver(PathConfig { drop_folders: vec!["**/inbox".into()], ..PathConfig::default() });
+
+        assert!(paths.resolve_upload_dir("paths-drop/inbox").await.is_ok());
+        assert!(matches!(paths.resolve("paths-drop/inbox").await, Err(AppError::PermissionDenied(_))));
+        let resolved = paths.resolve("paths-drop/inbox/upload.txt").await;
+        assert!(matches!(resolved, Err(AppError::PermissionDenied(_))));
+        assert!(!paths.is_visible(Path::new("paths-drop/inbox/upload.txt")));
+    }
+
+    #[tokio::test]
+    async fn new_files_need_an_accepted_name_and_a_parent() {
+        let dir = scratch_dir("paths-new");
+        let lenient = resolver(PathConfig::default());
+        let portable = resolver(PathConfig { new_names: NamePolicy::Portable, ..PathConfig::default() });
+
+        assert_eq!(lenient.resolve_new("paths-new/a.txt").await.unwrap(), dir.join("a.txt"));
+        assert!(matches!(lenient.resolve_new("paths-new/a\nb").await, Err(AppError::InvalidPath(_))));
+        assert!(matches!(lenient.resolve_new("paths-new/missing/a.txt").await, Err(AppError::Conflict(_))));
+        assert!(lenient.resolve_new("paths-new/nul.txt").await.is_ok());
+        for name in ["nul.txt", "COM1", "a:b", "trailing.", "trailing "] {
+            let resolved = portable.resolve_new(&format!("paths-new/{}", name)).await;
+            assert!(matches!(resolved, Err(AppError::InvalidPath(_))), "{}", name);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_follow_the_policy() {
+        use std::os::unix::fs::symlink;
+
+        let dir = scratch_dir("paths-symlinks");
+        std::fs::write(dir.join("target.txt"), "inside").unwrap();
+        symlink("target.txt", dir.join("inside")).unwrap();
+        let outside = std::env::temp_dir().join(format!("axum-webdav-outside-{}", std::process::id()));
+        std::fs::write(&outside, "outside").unwrap();
+        symlink(&outside, dir.join("outside")).unwrap();
+
+        let deny = resolver(PathConfig { symlinks: SymlinkPolicy::Deny, ..PathConfig::default() });
+        let inside_root = resolver(PathConfig { symlinks: SymlinkPolicy::InsideRoot, ..PathConfig::default() });
+        let follow = resolver(PathConfig { symlinks: SymlinkPolicy::Follow, ..PathConfig::default() });
+
+        assert!(matches!(deny.resolve("paths-symlinks/inside").await, Err(AppError::PermissionDenied(_))));
+        assert!(inside_root.resolve("paths-symlinks/inside").await.is_ok());
+        let resolved = inside_root.resolve("paths-symlinks/outside").await;
+        assert!(matches!(resolved, Err(AppError::PermissionDenied(_))));
+        assert!(follow.resolve("paths-symlinks/outside").await.is_ok());
+
+        // On Linux, opening enforces the policy too, for links swapped
+        // after the check
+        let checked = inside_root.resolve("paths-symlinks/target.txt").await.unwrap();
+        std::fs::remove_file(&checked).unwrap();
+        symlink(&outside, &checked).unwrap();
+        #[cfg(target_os = "linux")]
+        assert!(inside_root.open(&checked).await.is_err());
+        std::fs::remove_file(&outside).unwrap();
+    }
+
+    #[test]
+    fn glob_paths_use_slashes_without_dot_components() {
+        assert_eq!(glob_path(Path::new("./a/./b.txt")), "a/b.txt");
+        assert_eq!(glob_path(Path::new("")), "");
+    }
+}
@@ -0,0 +1,222 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method};
+
+use crate::error::AppError;
+
+// The validators of the resource a request is about
+#[derive(Debug, Clone, Copy)]
+pub struct Resource<'a> {
+    pub etag: Option<&'a str>,
+    pub modified: Option<SystemTime>,
+}
+
+// What the request should get once its preconditions are evaluated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evaluation {
+    Proceed,
+    // 304; only for GET and HEAD
+    NotModified,
+}
+
+// Evaluate the conditional request headers in the order RFC 9110 section
+// 13.2.2 gives, failing with 412 when a precondition does not hold. Every
+// handler answering for a resource goes through this, so the semantics are
+// the same whatever the method
+pub fn check_preconditions(
+    resource: Resource<'_>,
+    headers: &HeaderMap,
+    method: &Method,
+) -> Result<Evaluation, AppError> {
+    // If-Unmodified-Since only counts without If-Match
+    match headers.get(header::IF_MATCH) {
+        Some(value) if !if_match(resource, header_text(value)) => {
+            return Err(failed("If-Match does not match the current ETag"));
+        }
+        Some(_) => {}
+        None => {
+            if unmodified_since(resource, headers, header::IF_UNMODIFIED_SINCE) == Some(false) {
+                return Err(failed("Modified since the If-Unmodified-Since date"));
+            }
+        }
+    }
+
+    // Likewise If-Modified-Since only counts without If-None-Match, and only
+    // for GET and HEAD
+    let safe = method == Method::GET || method == Method::HEAD;
+    match headers.get(header::IF_NONE_MATCH) {
+        Some(value) if if_none_match_hit(resource, header_text(value)) => {
+            if !safe {
+                return Err(failed("If-None-Match matches the current ETag"));
+            }
+            return Ok(Evaluation::NotModified);
+        }
+        Some(_) => {}
+        None => {
+            if safe && unmodified_since(resource, headers, header::IF_MODIFIED_SINCE) == Some(true) {
+                return Ok(Evaluation::NotModified);
+            }
+        }
+    }
+    Ok(Evaluation::Proceed)
+}
+
+fn failed(reason: &str) -> AppError {
+    AppError::PreconditionFailed(reason.into())
+}
+
+// If-Match uses the strong comparison: weak tags never match, so a client
+// holding a fast tag cannot mistake a rewritten file for the one it had
+fn if_match(resource: Resource<'_>, value: &str) -> bool {
+    value == "*" || resource.etag.is_some_and(|etag| {
+        !is_weak(etag) && tags(value).any(|tag| !is_weak(tag) && tag == etag)
+    })
+}
+
+// If-None-Match uses the weak comparison, ignoring the W/ prefix
+fn if_none_match_hit(resource: Resource<'_>, value: &str) -> bool {
+    value == "*" || resource.etag.is_some_and(|etag| {
+        tags(value).any(|tag| opaque(tag) == opaque(etag))
+    })
+}
+
+fn header_text(value: &HeaderValue) -> &str {
+    value.to_str().unwrap_or_default().trim()
+}
+
+fn is_weak(tag: &str) -> bool {
+    tag.starts_with("W/")
+}
+
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+// Entity tags in a comma-separated list; commas inside quotes are part of
+// the tag
+fn tags(list: &str) -> impl Iterator<Item = &str> {
+    let mut rest = list;
+    std::iter::from_fn(move || {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return None;
+        }
+        let opening = rest.find('"')?;
+        let closing = opening + 1 + rest[opening + 1..].find('"')?;
+        let (tag, remaining) = rest.split_at(closing + 1);
+        rest = remaining;
+        Some(tag.trim())
+    })
+}
+
+// Whether the resource is unchanged since the date in header `name`, or
+// None without a usable date. HTTP dates that do not parse are ignored, as
+// RFC 9110 requires
+fn unmodified_since(resource: Resource<'_>, headers: &HeaderMap, name: HeaderName) -> Option<bool> {
+    let value = headers.get(name)?.to_str().ok()?;
+    let since = httpdate::parse_http_date(value).ok()?;
+    Some(seconds(resource.modified?) <= since)
+}
+
+// HTTP dates have whole seconds
+fn seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    const STRONG: &str = "\"abc\"";
+    const WEAK: &str = "W/\"abc\"";
+
+    // A resource last modified at 1000 seconds past the epoch, and a bit
+    fn resource(etag: Option<&str>) -> Resource<'_> {
+        Resource { etag, modified: Some(UNIX_EPOCH + Duration::from_millis(1_000_500)) }
+    }
+
+    fn date(secs: u64) -> String {
+        httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    fn check(
+        resource: Resource<'_>,
+        method: Method,
+        headers: &[(HeaderName, &str)],
+    ) -> Result<Evaluation, StatusCode> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(name.clone(), HeaderValue::from_str(value).unwrap());
+        }
+        check_preconditions(resource, &map, &method).map_err(|err| err.into_response().status())
+    }
+
+    #[test]
+    fn unconditional_requests_proceed() {
+        assert_eq!(check(resource(Some(STRONG)), Method::GET, &[]), Ok(Evaluation::Proceed));
+    }
+
+    #[test]
+    fn if_match_compares_strongly() {
+        let found = |etag, value| check(resource(etag), Method::PUT, &[(header::IF_MATCH, value)]);
+        assert_eq!(found(Some(STRONG), STRONG), Ok(Evaluation::Proceed));
+        assert_eq!(found(Some(STRONG), "\"other\", \"abc\""), Ok(Evaluation::Proceed));
+        assert_eq!(found(Some(STRONG), "*"), Ok(Evaluation::Proceed));
+        assert_eq!(found(Some(STRONG), WEAK), Err(StatusCode::PRECONDITION_FAILED));
+        assert_eq!(found(Some(WEAK), WEAK), Err(StatusCode::PRECONDITION_FAILED));
+        assert_eq!(found(Some(STRONG), "\"other\""), Err(StatusCode::PRECONDITION_FAILED));
+        assert_eq!(found(None, STRONG), Err(StatusCode::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn if_none_match_compares_weakly() {
+        let get = |value| check(resource(Some(STRONG)), Method::GET, &[(header::IF_NONE_MATCH, value)]);
+        assert_eq!(get(STRONG), Ok(Evaluation::NotModified));
+        assert_eq!(get(WEAK), Ok(Evaluation::NotModified));
+        assert_eq!(get("*"), Ok(Evaluation::NotModified));
+        assert_eq!(get("\"a,b\", \"other\""), Ok(Evaluation::Proceed));
+    }
+
+    #[test]
+    fn if_none_match_fails_unsafe_methods() {
+        let put = check(resource(Some(STRONG)), Method::PUT, &[(header::IF_NONE_MATCH, "*")]);
+        assert_eq!(put, Err(StatusCode::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn dates_compare_in_whole_seconds() {
+        let since = |name: HeaderName, secs| check(resource(None), Method::GET, &[(name, &date(secs))]);
+        assert_eq!(since(header::IF_MODIFIED_SINCE, 1000), Ok(Evaluation::NotModified));
+        assert_eq!(since(header::IF_MODIFIED_SINCE, 999), Ok(Evaluation::Proceed));
+        assert_eq!(since(header::IF_UNMODIFIED_SINCE, 1000), Ok(Evaluation::Proceed));
+        assert_eq!(since(header::IF_UNMODIFIED_SINCE, 999), Err(StatusCode::PRECONDITION_FAILED));
+    }
+
+    #[test]
+    fn dates_give_way_to_tags() {
+        let tagged = resource(Some(STRONG));
+        // If-Match passing makes an old If-Unmodified-Since irrelevant
+        let headers = [(header::IF_MATCH, STRONG), (header::IF_UNMODIFIED_SINCE, &*date(0))];
+        assert_eq!(check(tagged, Method::PUT, &headers), Ok(Evaluation::Proceed));
+        // As If-None-Match missing does a recent If-Modified-Since
+        let headers = [(header::IF_NONE_MATCH, "\"other\""), (header::IF_MODIFIED_SINCE, &*date(2000))];
+        assert_eq!(check(tagged, Method::GET, &headers), Ok(Evaluation::Proceed));
+    }
+
+    #[test]
+    fn if_modified_since_only_applies_to_reads() {
+        let headers = [(header::IF_MODIFIED_SINCE, &*date(2000))];
+        assert_eq!(check(resource(None), Method::HEAD, &headers), Ok(Evaluation::NotModified));
+        assert_eq!(check(resource(None), Method::PUT, &headers), Ok(Evaluation::Proceed));
+    }
+
+    #[test]
+    fn unparsable_dates_are_ignored() {
+        let headers = [(header::IF_UNMODIFIED_SINCE, "yesterday")];
+        assert_eq!(check(resource(None), Method::PUT, &headers), Ok(Evaluation::Proceed));
+    }
+}
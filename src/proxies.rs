@@ -0,0 +1,238 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{header::HeaderName, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{
+    config::{ConfigError, ProxyConfig},
+    listener::ConnectionInfo,
+    state::AppState,
+};
+
+const FORWARDED: HeaderName = HeaderName::from_static("forwarded");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+// Reverse proxies whose request headers are believed
+#[derive(Debug)]
+pub struct Proxies {
+    trusted: Vec<Network>,
+}
+
+// An address block in CIDR notation; a plain address is a block of one
+#[derive(Debug, Clone, Copy)]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(text: &str) -> Option<Network> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (text.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+        (prefix <= bits).then_some(Network { addr, prefix })
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        // IPv4 clients on a dual-stack socket show up as mapped IPv6
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            addr => addr,
+        };
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) =>
+                masked(u32::from(net).into(), 32, self.prefix) == masked(u32::from(addr).into(), 32, self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(addr)) =>
+                masked(net.into(), 128, self.prefix) == masked(addr.into(), 128, self.prefix),
+            _ => false,
+        }
+    }
+}
+
+// The top `prefix` bits of a `bits` wide address
+fn masked(addr: u128, bits: u32, prefix: u32) -> u128 {
+    match prefix {
+        0 => 0,
+        prefix => addr >> (bits - prefix),
+    }
+}
+
+impl Proxies {
+    pub fn from_config(config: &ProxyConfig) -> Result<Proxies, ConfigError> {
+        let trusted = config.trusted.iter()
+            .map(|text| Network::parse(text).ok_or_else(|| ConfigError::Invalid(format!(
+                "proxies.trusted: {:?} is not an address or CIDR block", text,
+            ))))
+            .collect::<Result<_, _>>()?;
+        Ok(Proxies { trusted })
+    }
+
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted.iter().any(|network| network.contains(addr))
+    }
+
+    // The address of the client a request came from. Forwarding headers
+    // are followed back from the peer only through trusted proxies, so an
+    // untrusted client cannot claim another address
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let hops = match headers.contains_key(FORWARDED) {
+            true => forwarded_for(headers),
+            false => x_forwarded_for(headers),
+        };
+
+        // Nearest hop first; stop at the first one not vouched for
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            if !self.is_trusted(client) {
+                break;
+            }
+            match hop {
+                Some(addr) => client = *addr,
+                // Obfuscated or garbled; the proxy that added it is as far
+                // back as we can tell
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+// Header values of every occurrence of `name`, split at commas
+fn list_items(headers: &HeaderMap, name: HeaderName) -> impl Iterator<Item = &str> {
+    headers.get_all(name).into_iter()
+        .flat_map(|value| value.to_str().unwrap_or("").split(','))
+        .map(str::trim)
+}
+
+// Addresses from X-Forwarded-For, client first
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    list_items(headers, X_FORWARDED_FOR).map(parse_node).collect()
+}
+
+// The for= addresses from RFC 7239 Forwarded, client first
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    list_items(headers, FORWARDED)
+        .map(|element| {
+            element.split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, value)| parse_node(value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+// An address as proxies write it: bare, "[v6]" or with a port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Some(v6) = node.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+        return v6.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
+
+// Address of the client behind any trusted proxies, kept in the request's
+// extensions
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+// Middleware recording the client address for the layers and handlers below
+pub async fn resolve_client<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = state.proxies.client_ip(connection.remote_addr.ip(), req.headers());
+    req.extensions_mut().insert(ClientIp(client));
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(trusted: &[&str]) -> Proxies {
+        Proxies::from_config(&ProxyConfig {
+            trusted: trusted.iter().map(|text| text.to_string()).collect(),
+        }).unwrap()
+    }
+
+    fn headers(name: HeaderName, values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn networks_match_by_prefix() {
+        let proxies = proxies(&["10.0.0.0/8", "192.168.1.1", "fd00::/8"]);
+        assert!(proxies.is_trusted(ip("10.1.2.3")));
+        assert!(proxies.is_trusted(ip("192.168.1.1")));
+        assert!(!proxies.is_trusted(ip("192.168.1.2")));
+        assert!(proxies.is_trusted(ip("fd12::1")));
+        assert!(!proxies.is_trusted(ip("fe80::1")));
+        // IPv4 clients on a dual-stack socket
+        assert!(proxies.is_trusted(ip("::ffff:10.0.0.1")));
+    }
+
+    #[test]
+    fn invalid_networks_are_rejected() {
+        for text in ["10.0.0.0/33", "::/129", "proxy.example", "10.0.0.0/"] {
+            let config = ProxyConfig { trusted: vec![text.to_string()] };
+            assert!(Proxies::from_config(&config).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_claim_addresses() {
+        let headers = headers(X_FORWARDED_FOR, &["203.0.113.7"]);
+        assert_eq!(proxies(&["10.0.0.1"]).client_ip(ip("198.51.100.1"), &headers), ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn forwarded_for_is_followed_through_trusted_hops_only() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        // Spoofed first entry, then the real client, then an inner proxy
+        let headers = headers(X_FORWARDED_FOR, &["1.2.3.4, 203.0.113.7", "10.0.0.2"]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &HeaderMap::new()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_over_x_forwarded_for() {
+        let proxies = proxies(&["10.0.0.1"]);
+        let mut headers = headers(FORWARDED, &["for=\"[2001:db8::1]:4711\";proto=https"]);
+        headers.insert(X_FORWARDED_FOR, "203.0.113.7".parse().unwrap());
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &headers), ip("2001:db8::1"));
+
+        let headers = self::headers(FORWARDED, &["for=192.0.2.60:8080;by=10.0.0.1"]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &headers), ip("192.0.2.60"));
+    }
+
+    #[test]
+    fn obfuscated_hops_stop_the_walk() {
+        let proxies = proxies(&["10.0.0.0/8"]);
+        let headers = headers(FORWARDED, &["for=203.0.113.7, for=_hidden", "for=10.0.0.2"]);
+        assert_eq!(proxies.client_ip(ip("10.0.0.1"), &headers), ip("10.0.0.2"));
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    Extension,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{config::RateLimitConfig, error::AppError, proxies::ClientIp, state::AppState};
+
+// Most clients tracked per budget; past it, the one tracked longest is
+// forgotten, as if its bucket were full again
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+// How often buckets that filled up again are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    buckets: HashMap<IpAddr, Bucket>,
+    // The tracked clients, in the order they were first seen
+    order: VecDeque<IpAddr>,
+}
+
+// Token buckets per client IP, refilled at `rate` tokens per second
+#[derive(Debug)]
+struct Buckets {
+    rate: f64,
+    burst: f64,
+    max_clients: usize,
+    clients: Mutex<Clients>,
+}
+
+impl Buckets {
+    fn new(rate: f64, burst: f64) -> Option<Buckets> {
+        (rate > 0.0).then(|| Buckets {
+            rate,
+            burst: burst.max(1.0),
+            max_clients: MAX_TRACKED_CLIENTS,
+            clients: Mutex::default(),
+        })
+    }
+
+    // Take one token for `ip`, or return the seconds until one is available
+    fn take(&self, ip: IpAddr) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut guard = self.clients.lock().unwrap();
+        let clients = &mut *guard;
+
+        if !clients.buckets.contains_key(&ip) {
+            if clients.buckets.len() >= self.max_clients {
+                if let Some(oldest) = clients.order.pop_front() {
+                    clients.buckets.remove(&oldest);
+                }
+            }
+            clients.order.push_back(ip);
+        }
+        let bucket = clients.buckets.entry(ip).or_insert(Bucket { tokens: self.burst, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.rate).ceil() as u64)
+        }
+    }
+
+    // Forget the clients whose buckets are full again, which is what new
+    // clients get anyway
+    fn prune(&self) {
+        let now = Instant::now();
+        let mut guard = self.clients.lock().unwrap();
+        let clients = &mut *guard;
+        let (rate, burst) = (self.rate, self.burst);
+        clients.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < burst
+        });
+        let buckets = &clients.buckets;
+        clients.order.retain(|ip| buckets.contains_key(ip));
+    }
+}
+
+// Separate budgets for reading and modifying requests
+#[derive(Debug)]
+pub struct RateLimits {
+    read: Option<Buckets>,
+    write: Option<Buckets>,
+}
+
+impl RateLimits {
+    pub fn from_config(config: &RateLimitConfig) -> RateLimits {
+        RateLimits {
+            read: Buckets::new(config.read_per_sec, config.read_burst),
+            write: Buckets::new(config.write_per_sec, config.write_burst),
+        }
+    }
+}
+
+// Prune the buckets every so often, for as long as the server runs
+pub async fn prune(state: Arc<AppState>) {
+    let limits = &state.rate_limits;
+    if limits.read.is_none() && limits.write.is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        limits.read.iter().chain(&limits.write).for_each(Buckets::prune);
+    }
+}
+
+// Middleware answering 429 to clients over their request budget
+pub async fn limit_rate<B>(
+    State(state): State<Arc<AppState>>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let buckets = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => &state.rate_limits.read,
+        _ => &state.rate_limits.write,
+    };
+
+    if let Some(Err(wait)) = buckets.as_ref().map(|buckets| buckets.take(client)) {
+        let mut response = AppError::Rejected(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many requests, slow down".into(),
+        ).into_response();
+        response.headers_mut().insert(header::RETRY_AFTER, wait.max(1).into());
+        return response;
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(n: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, n])
+    }
+
+    #[test]
+    fn bursts_are_allowed_then_limited() {
+        let buckets = Buckets::new(1.0, 3.0).unwrap();
+        for _ in 0..3 {
+            assert_eq!(buckets.take(client(1)), Ok(()));
+        }
+        assert_eq!(buckets.take(client(1)), Err(1));
+        // Other clients have budgets of their own
+        assert_eq!(buckets.take(client(2)), Ok(()));
+    }
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let buckets = Buckets::new(1000.0, 1.0).unwrap();
+        assert_eq!(buckets.take(client(1)), Ok(()));
+        assert!(buckets.take(client(1)).is_err());
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(buckets.take(client(1)), Ok(()));
+    }
+
+    #[test]
+    fn tracked_clients_are_capped() {
+        let mut buckets = Buckets::new(0.001, 1.0).unwrap();
+        buckets.max_clients = 3;
+        for n in 0..10 {
+            assert_eq!(buckets.take(client(n)), Ok(()));
+        }
+        let clients = buckets.clients.lock().unwrap();
+        assert_eq!(clients.buckets.len(), 3);
+        assert_eq!(clients.order, [client(7), client(8), client(9)]);
+    }
+
+    #[test]
+    fn pruning_forgets_full_buckets_only() {
+        let buckets = Buckets::new(0.001, 2.0).unwrap();
+        buckets.take(client(1)).unwrap();
+        buckets.take(client(2)).unwrap();
+        buckets.clients.lock().unwrap().buckets.get_mut(&client(2)).unwrap().tokens = 2.0;
+
+        buckets.prune();
+        let clients = buckets.clients.lock().unwrap();
+        assert_eq!(clients.order, [client(1)]);
+        assert!(clients.buckets.contains_key(&client(1)));
+    }
+}
@@ -0,0 +1,48 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{listener::ConnectionInfo, state::AppState};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longest incoming ID accepted; longer ones are replaced
+const MAX_LEN: usize = 200;
+
+// Identifier of a request, kept in its extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// Middleware giving every request an ID and echoing it in the response. An
+// ID sent by a trusted proxy is kept so it matches the proxy's own logs
+pub async fn assign<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    mut req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let incoming = state.proxies.is_trusted(connection.remote_addr.ip())
+        .then(|| req.headers().get(X_REQUEST_ID))
+        .flatten()
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid(id));
+    let id = match incoming {
+        Some(id) => id.to_string(),
+        None => Uuid::new_v4().to_string(),
+    };
+
+    req.extensions_mut().insert(RequestId(id.clone()));
+    let mut response = next.run(req).await;
+    response.headers_mut().insert(X_REQUEST_ID, HeaderValue::from_str(&id).unwrap());
+    response
+}
+
+// Visible ASCII only, so the ID is safe in headers and log lines
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
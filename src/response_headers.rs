@@ -0,0 +1,121 @@
+use axum::http::{header, HeaderName, HeaderValue};
+use globset::GlobSet;
+use std::path::Path;
+
+use crate::{
+    config::{ConfigError, HeaderRule},
+    paths,
+};
+
+// Extra headers for served files, chosen by path
+#[derive(Debug)]
+pub struct ResponseHeaders {
+    paths: GlobSet,
+    // Headers of each rule, in the order of `paths`
+    headers: Vec<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl ResponseHeaders {
+    pub fn from_config(rules: &[HeaderRule]) -> Result<ResponseHeaders, ConfigError> {
+        let patterns = rules.iter().map(|rule| rule.path.clone()).collect::<Vec<_>>();
+        let headers = rules.iter().map(parse_rule).collect::<Result<_, _>>()?;
+
+        Ok(ResponseHeaders {
+            paths: paths::build_globs(&patterns)?,
+            headers,
+        })
+    }
+
+    // Headers of every rule matching the path, in rule order, so later
+    // rules win when they set the same header
+    pub fn headers_for(&self, path: &Path) -> impl Iterator<Item = &(HeaderName, HeaderValue)> {
+        let mut matches = self.paths.matches(paths::glob_path(path));
+        matches.sort_unstable();
+        matches.into_iter().flat_map(|rule| &self.headers[rule])
+    }
+}
+
+// Headers describing the body and its version, which only the server can
+// get right: the wrong length or range breaks the response, and the wrong
+// validators make caches keep stale copies
+const RESERVED: [HeaderName; 7] = [
+    header::CONTENT_LENGTH,
+    header::CONTENT_RANGE,
+    header::CONTENT_TYPE,
+    header::CONTENT_ENCODING,
+    header::TRANSFER_ENCODING,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+fn parse_rule(rule: &HeaderRule) -> Result<Vec<(HeaderName, HeaderValue)>, ConfigError> {
+    let invalid = |what: &str, text: &str| ConfigError::Invalid(format!(
+        "headers rule for {:?}: invalid header {} {:?}", rule.path, what, text,
+    ));
+    if rule.set.is_empty() {
+        return Err(ConfigError::Invalid(format!("headers rule for {:?} sets no header", rule.path)));
+    }
+    rule.set.iter()
+        .map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| invalid("name", name))?;
+            if RESERVED.contains(&name) {
+                return Err(ConfigError::Invalid(format!(
+                    "headers rule for {:?} cannot set {}; content types are configured in [mime]",
+                    rule.path, name,
+                )));
+            }
+            Ok((name, HeaderValue::from_str(value).map_err(|_| invalid("value", value))?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn rule(path: &str, set: &[(&str, &str)]) -> HeaderRule {
+        HeaderRule {
+            path: path.to_string(),
+            set: set.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    fn headers_for(headers: &ResponseHeaders, path: &str) -> Vec<(String, String)> {
+        headers.headers_for(Path::new(path))
+            .map(|(name, value)| (name.to_string(), value.to_str().unwrap().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn matching_rules_apply_in_order() {
+        let headers = ResponseHeaders::from_config(&[
+            rule("**/*.mp4", &[("Cross-Origin-Resource-Policy", "cross-origin")]),
+            rule("public/**", &[("Access-Control-Allow-Origin", "*"), ("X-Robots-Tag", "noindex")]),
+            rule("public/**", &[("X-Robots-Tag", "all")]),
+        ]).unwrap();
+
+        assert_eq!(headers_for(&headers, "public/a.mp4"), [
+            ("cross-origin-resource-policy".to_string(), "cross-origin".to_string()),
+            ("access-control-allow-origin".to_string(), "*".to_string()),
+            ("x-robots-tag".to_string(), "noindex".to_string()),
+            ("x-robots-tag".to_string(), "all".to_string()),
+        ]);
+        assert!(headers_for(&headers, "private/a.txt").is_empty());
+    }
+
+    #[test]
+    fn framing_and_validator_headers_are_rejected() {
+        for name in ["Content-Length", "content-type", "ETag", "Content-Range", "Last-Modified"] {
+            let result = ResponseHeaders::from_config(&[rule("**", &[(name, "1")])]);
+            assert!(matches!(result, Err(ConfigError::Invalid(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn rules_must_set_valid_headers() {
+        assert!(ResponseHeaders::from_config(&[HeaderRule { path: "**".into(), set: BTreeMap::new() }]).is_err());
+        assert!(ResponseHeaders::from_config(&[rule("**", &[("Bad Name", "x")])]).is_err());
+        assert!(ResponseHeaders::from_config(&[rule("**", &[("X-Ok", "bad\nvalue")])]).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::VecDeque, path::PathBuf, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::{
+    config::SearchConfig,
+    error::{self, AppError},
+    paths,
+    state::AppState,
+};
+
+// File name search below a directory, for clients that only need to find
+// files by name
+#[derive(Debug)]
+pub struct Search {
+    max_results: usize,
+    max_depth: usize,
+    // Directory entries one search may visit; 0 means unlimited
+    max_entries: usize,
+}
+
+impl Search {
+    pub fn from_config(config: &SearchConfig, max_entries: usize) -> Search {
+        Search {
+            max_results: config.max_results,
+            max_depth: config.max_depth,
+            max_entries,
+        }
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/.search", get(search)
+        .fallback(error::method_not_allowed("GET")))
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    // Glob matched against file names, ignoring case
+    name: String,
+    // Directory to search; the whole served directory when empty
+    #[serde(default)]
+    under: String,
+}
+
+#[derive(Debug, Serialize)]
+struct SearchResults {
+    results: Vec<Match>,
+    // Whether the search stopped at the result or depth cap
+    truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Match {
+    path: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+}
+
+// Walk the tree breadth-first, so shallow matches come first and the caps
+// cut off the deepest ones. Only what clients could fetch is searched
+async fn search(
+    State(state): State<Arc<AppState>>,
+    query: Option<Query<SearchQuery>>,
+) -> Result<Json<SearchResults>, AppError> {
+    let search = state.search.as_ref().ok_or_else(|| AppError::NotFound(".search".into()))?;
+    let Some(Query(query)) = query else {
+        return Err(AppError::Rejected(
+            StatusCode::BAD_REQUEST,
+            "Search needs a name pattern, as in ?name=*.pdf".into(),
+        ));
+    };
+    let pattern = matcher(&query.name)?;
+    let paths = state.paths.get();
+    let under = match query.under.trim_matches('/') {
+        "" => PathBuf::new(),
+        under => {
+            let dir = paths.resolve(under).await?;
+            if !fs::metadata(&dir).await.map_err(|err| AppError::from_io(err, &dir))?.is_dir() {
+                return Err(AppError::InvalidPath(format!("{} is not a directory", dir.display())));
+            }
+            dir
+        }
+    };
+
+    let mut found = SearchResults { results: Vec::new(), truncated: false };
+    let mut pending = VecDeque::from([(under, 0)]);
+    let mut visited = 0;
+    while let Some((dir, depth)) = pending.pop_front() {
+        let listed = if dir.as_os_str().is_empty() { PathBuf::from(".") } else { dir.clone() };
+        let Ok(mut entries) = fs::read_dir(&listed).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // Unlike the result and depth caps, which leave a useful partial
+            // answer, this one means the search is too broad
+            visited += 1;
+            if search.max_entries > 0 && visited > search.max_entries {
+                return Err(AppError::Rejected(StatusCode::FORBIDDEN, format!(
+                    "Search visits more than {} entries; narrow it with under=", search.max_entries,
+                )));
+            }
+            // Skip whatever the path policies hide
+            let Some(request_path) = dir.join(entry.file_name()).to_str().map(str::to_owned) else {
+                continue;
+            };
+            let Ok(path) = paths.resolve(&request_path).await else {
+                continue;
+            };
+            let Ok(metadata) = fs::metadata(&path).await else {
+                continue;
+            };
+
+            if pattern.is_match(entry.file_name()) {
+                if found.results.len() == search.max_results {
+                    found.truncated = true;
+                    return Ok(Json(found));
+                }
+                found.results.push(Match {
+                    path: paths::glob_path(&path),
+                    kind: if metadata.is_dir() { "directory" } else { "file" },
+                    size: if metadata.is_dir() { 0 } else { metadata.len() },
+                });
+            }
+
+            // Symlinked directories could loop forever
+            if metadata.is_dir() && !entry.file_type().await.is_ok_and(|kind| kind.is_symlink()) {
+                if depth + 1 < search.max_depth {
+                    pending.push_back((path, depth + 1));
+                } else {
+                    found.truncated = true;
+                }
+            }
+        }
+    }
+    Ok(Json(found))
+}
+
+fn matcher(pattern: &str) -> Result<GlobMatcher, AppError> {
+    GlobBuilder::new(pattern)
+        .case_insensitive(true)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|err| AppError::Rejected(StatusCode::BAD_REQUEST, format!("Invalid name pattern: {}", err)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, testing::scratch_dir};
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    fn app(config: SearchConfig) -> Router {
+        let state = AppState::from_config(&Config { search: config, ..Config::default() }).unwrap();
+        routes().with_state(Arc::new(state))
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(req).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn paths(results: &serde_json::Value) -> Vec<&str> {
+        results["results"].as_array().unwrap().iter().map(|found| found["path"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn names_match_shallowest_first_ignoring_case() {
+        let dir = scratch_dir("search-names");
+        std::fs::create_dir_all(dir.join("docs/old")).unwrap();
+        for file in ["A.PDF", "docs/b.pdf", "docs/old/c.pdf", "docs/notes.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let app = app(SearchConfig { enabled: true, ..SearchConfig::default() });
+
+        let (status, results) = get(&app, "/.search?name=*.pdf&under=/search-names").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(paths(&results), ["search-names/A.PDF", "search-names/docs/b.pdf", "search-names/docs/old/c.pdf"]);
+        assert_eq!(results["truncated"], false);
+
+        let (_, results) = get(&app, "/.search?name=*.pdf&under=/search-names/docs").await;
+        assert_eq!(paths(&results), ["search-names/docs/b.pdf", "search-names/docs/old/c.pdf"]);
+    }
+
+    #[tokio::test]
+    async fn caps_truncate_the_results() {
+        let dir = scratch_dir("search-caps");
+        std::fs::create_dir_all(dir.join("deep")).unwrap();
+        for file in ["a.txt", "b.txt", "deep/c.txt"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let app = app(SearchConfig { enabled: true, max_results: 10, max_depth: 1 });
+
+        let (_, results) = get(&app, "/.search?name=*.txt&under=search-caps").await;
+        assert_eq!(paths(&results).len(), 2);
+        assert_eq!(results["truncated"], true);
+    }
+
+    #[tokio::test]
+    async fn bad_queries_are_rejected() {
+        scratch_dir("search-bad");
+        let app = app(SearchConfig { enabled: true, ..SearchConfig::default() });
+
+        assert_eq!(get(&app, "/.search").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get(&app, "/.search?under=docs").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get(&app, "/.search?name=[").await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(get(&app, "/.search?name=*&under=search-bad/missing").await.0, StatusCode::NOT_FOUND);
+    }
+}
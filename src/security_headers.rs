@@ -0,0 +1,151 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderName, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+use crate::{
+    config::{ConfigError, SecurityHeaderConfig},
+    state::AppState,
+};
+
+// Marks HTML the server writes itself, such as error pages, as opposed to
+// files served from disk; only these get the Content-Security-Policy
+#[derive(Debug, Clone, Copy)]
+pub struct GeneratedHtml;
+
+// Hardening headers added to every response
+#[derive(Debug)]
+pub struct SecurityHeaders {
+    always: Vec<(HeaderName, HeaderValue)>,
+    content_security_policy: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    pub fn from_config(config: &SecurityHeaderConfig) -> Result<SecurityHeaders, ConfigError> {
+        let mut always = Vec::new();
+        if config.hsts_max_age_secs > 0 {
+            let mut hsts = format!("max-age={}", config.hsts_max_age_secs);
+            if config.hsts_include_subdomains {
+                hsts.push_str("; includeSubDomains");
+            }
+            if config.hsts_preload {
+                hsts.push_str("; preload");
+            }
+            always.push((header::STRICT_TRANSPORT_SECURITY, HeaderValue::try_from(hsts).unwrap()));
+        }
+        if config.content_type_options {
+            always.push((header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")));
+        }
+        if let Some(policy) = value(&config.referrer_policy, "referrer_policy")? {
+            always.push((header::REFERRER_POLICY, policy));
+        }
+
+        Ok(SecurityHeaders {
+            always,
+            content_security_policy: value(&config.content_security_policy, "content_security_policy")?,
+        })
+    }
+}
+
+// A configured header value; empty means the header is not sent
+fn value(text: &str, setting: &str) -> Result<Option<HeaderValue>, ConfigError> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    HeaderValue::from_str(text).map(Some).map_err(|_| {
+        ConfigError::Invalid(format!("security_headers.{} is not a valid header value", setting))
+    })
+}
+
+// Middleware adding the security headers, leaving any a handler set alone
+pub async fn add<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(req).await;
+    let generated = response.extensions().get::<GeneratedHtml>().is_some();
+    let security = &state.security_headers;
+    let headers = response.headers_mut();
+    for (name, value) in &security.always {
+        headers.entry(name).or_insert_with(|| value.clone());
+    }
+    if let (true, Some(policy)) = (generated, &security.content_security_policy) {
+        headers.entry(header::CONTENT_SECURITY_POLICY).or_insert_with(|| policy.clone());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{body::Body, middleware, response::IntoResponse, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app(config: SecurityHeaderConfig) -> Router {
+        let state = AppState::from_config(&Config { security_headers: config, ..Config::default() }).unwrap();
+        let page = || async {
+            let mut response = "<html></html>".into_response();
+            response.extensions_mut().insert(GeneratedHtml);
+            response
+        };
+        let referrer = || async { ([(header::REFERRER_POLICY, "no-referrer")], "a") };
+        Router::new()
+            .route("/file", get(|| async { "a" }))
+            .route("/page", get(page))
+            .route("/referrer", get(referrer))
+            .layer(middleware::from_fn_with_state(Arc::new(state), add))
+    }
+
+    async fn get_headers(app: &Router, uri: &str) -> axum::http::HeaderMap {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(req).await.unwrap().headers().clone()
+    }
+
+    #[tokio::test]
+    async fn only_generated_pages_get_a_policy() {
+        let app = app(SecurityHeaderConfig::default());
+
+        let file = get_headers(&app, "/file").await;
+        assert_eq!(file[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(file[header::REFERRER_POLICY], "strict-origin-when-cross-origin");
+        assert!(!file.contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(!file.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        let page = get_headers(&app, "/page").await;
+        assert!(page[header::CONTENT_SECURITY_POLICY].to_str().unwrap().starts_with("default-src 'none'"));
+    }
+
+    #[tokio::test]
+    async fn handlers_keep_their_own_headers() {
+        let headers = get_headers(&app(SecurityHeaderConfig::default()), "/referrer").await;
+        assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+    }
+
+    #[tokio::test]
+    async fn headers_follow_the_config() {
+        let app = app(SecurityHeaderConfig {
+            hsts_max_age_secs: 31536000,
+            hsts_include_subdomains: true,
+            hsts_preload: true,
+            content_type_options: false,
+            referrer_policy: String::new(),
+            content_security_policy: String::new(),
+        });
+
+        let page = get_headers(&app, "/page").await;
+        assert_eq!(page[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000; includeSubDomains; preload");
+        assert!(!page.contains_key(header::X_CONTENT_TYPE_OPTIONS));
+        assert!(!page.contains_key(header::REFERRER_POLICY));
+        assert!(!page.contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        let config = SecurityHeaderConfig { referrer_policy: "bad\nvalue".into(), ..SecurityHeaderConfig::default() };
+        assert!(matches!(SecurityHeaders::from_config(&config), Err(ConfigError::Invalid(_))));
+    }
+}
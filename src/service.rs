@@ -0,0 +1,73 @@
+use std::{ffi::OsString, sync::OnceLock, time::Duration};
+
+use tokio::sync::Notify;
+use windows_service::{
+    define_windows_service,
+    service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    },
+    service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+    service_dispatcher,
+};
+
+use crate::{config::Config, Cli};
+
+pub const NAME: &str = "axum-webdav";
+
+// What the service runs with; the dispatcher passes only the arguments
+// given to `sc.exe start`
+static LAUNCH: OnceLock<(Cli, Config)> = OnceLock::new();
+
+// Set when the service control manager asks the server to stop
+static STOP: Notify = Notify::const_new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+// Hand the thread to the service control manager until the service stops
+pub fn run(cli: Cli, config: Config) -> windows_service::Result<()> {
+    let _ = LAUNCH.set((cli, config));
+    service_dispatcher::start(NAME, ffi_service_main)
+}
+
+// Resolves once the service is asked to stop, like a shutdown signal
+pub async fn stopped() {
+    STOP.notified().await;
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some((cli, config)) = LAUNCH.get().cloned() else {
+        return;
+    };
+    let handler = |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            // Stored as a permit if the server is not waiting yet
+            STOP.notify_one();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    };
+    let Ok(status) = service_control_handler::register(NAME, handler) else {
+        return;
+    };
+
+    report(&status, ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN);
+    match crate::build_runtime(&config.runtime) {
+        Ok(runtime) => runtime.block_on(crate::serve(cli, config)),
+        Err(err) => eprintln!("Service error: {}", err),
+    }
+    report(&status, ServiceState::Stopped, ServiceControlAccept::empty());
+}
+
+fn report(status: &ServiceStatusHandle, state: ServiceState, controls: ServiceControlAccept) {
+    let _ = status.set_service_status(ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted: controls,
+        exit_code: ServiceExitCode::Win32(0),
+        checkpoint: 0,
+        wait_hint: Duration::default(),
+        process_id: None,
+    });
+}
@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::fs;
+
+use crate::{config::FileConfig, error::AppError, timing};
+
+// What the server needs to know about a path before opening it, and all
+// it needs to answer without opening it
+#[derive(Debug, Clone, Copy)]
+pub struct Stat {
+    pub is_file: bool,
+    pub is_dir: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    // Whether nobody may write to it
+    pub readonly: bool,
+    // Device and inode numbers, on Unix
+    pub file_id: Option<(u64, u64)>,
+}
+
+impl From<&Metadata> for Stat {
+    fn from(metadata: &Metadata) -> Stat {
+        Stat {
+            is_file: metadata.is_file(),
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            readonly: metadata.permissions().readonly(),
+            file_id: file_id(metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// File metadata remembered for a short while, so clients checking the same
+// entries over and over do not cost a stat call each time
+#[derive(Debug)]
+pub struct StatCache {
+    // None when caching is disabled
+    ttl: Option<Duration>,
+    max_entries: usize,
+    entries: Mutex<HashMap<PathBuf, (Instant, Stat)>>,
+}
+
+impl StatCache {
+    pub fn from_config(config: &FileConfig) -> StatCache {
+        StatCache {
+            ttl: (config.stat_cache_ttl_ms > 0 && config.stat_cache_entries > 0)
+                .then(|| Duration::from_millis(config.stat_cache_ttl_ms)),
+            max_entries: config.stat_cache_entries,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Metadata of `path`, following symlinks
+    pub async fn stat(&self, path: &Path) -> Result<Stat, AppError> {
+        let Some(ttl) = self.ttl else {
+            return Self::read(path).await;
+        };
+
+        if let Some((fetched, stat)) = self.entries.lock().unwrap().get(path) {
+            if fetched.elapsed() < ttl {
+                return Ok(*stat);
+            }
+        }
+
+        let stat = Self::read(path).await?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries {
+            // Drop whatever expired, and if that is not enough start over
+            entries.retain(|_, (fetched, _)| fetched.elapsed() < ttl);
+            if entries.len() >= self.max_entries {
+                entries.clear();
+            }
+        }
+        entries.insert(path.to_path_buf(), (Instant::now(), stat));
+        Ok(stat)
+    }
+
+    // Forget a path the server just changed
+    pub fn invalidate(&self, path: &Path) {
+        if self.ttl.is_some() {
+            self.entries.lock().unwrap().remove(path);
+        }
+    }
+
+    async fn read(path: &Path) -> Result<Stat, AppError> {
+        let metadata = timing::fs_wait(fs::metadata(path)).await
+            .map_err(|err| AppError::from_io(err, path))?;
+        Ok(Stat::from(&metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn cache(ttl_ms: u64, entries: usize) -> StatCache {
+        StatCache::from_config(&FileConfig {
+            stat_cache_ttl_ms: ttl_ms,
+            stat_cache_entries: entries,
+            ..FileConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn stats_are_remembered_until_invalidated() {
+        let file = scratch_dir("stat_cache_invalidate").join("a.txt");
+        std::fs::write(&file, "a").unwrap();
+        let cache = cache(60_000, 100);
+        assert_eq!(cache.stat(&file).await.unwrap().len, 1);
+
+        std::fs::write(&file, "abc").unwrap();
+        assert_eq!(cache.stat(&file).await.unwrap().len, 1);
+        cache.invalidate(&file);
+        assert_eq!(cache.stat(&file).await.unwrap().len, 3);
+    }
+
+    #[tokio::test]
+    async fn stats_expire() {
+        let file = scratch_dir("stat_cache_expire").join("a.txt");
+        std::fs::write(&file, "a").unwrap();
+        let cache = cache(50, 100);
+        cache.stat(&file).await.unwrap();
+
+        std::fs::write(&file, "abc").unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(cache.stat(&file).await.unwrap().len, 3);
+    }
+
+    #[tokio::test]
+    async fn a_full_cache_starts_over() {
+        let dir = scratch_dir("stat_cache_full");
+        let cache = cache(60_000, 2);
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.join(name), "a").unwrap();
+            cache.stat(&dir.join(name)).await.unwrap();
+        }
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn zero_disables_caching() {
+        let file = scratch_dir("stat_cache_disabled").join("a.txt");
+        std::fs::write(&file, "a").unwrap();
+        let cache = cache(0, 100);
+        cache.stat(&file).await.unwrap();
+
+        std::fs::write(&file, "abc").unwrap();
+        assert_eq!(cache.stat(&file).await.unwrap().len, 3);
+        assert!(cache.entries.lock().unwrap().is_empty());
+    }
+}
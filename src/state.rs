@@ -0,0 +1,199 @@
+use std::{
+    path::Path,
+    sync::{atomic::AtomicBool, Arc, RwLock},
+    time::Duration,
+};
+
+use tower_http::cors::CorsLayer;
+
+use crate::{
+    access_log::AccessLog,
+    admin::Admin,
+    archive::Archiver,
+    cache_control::CacheControl,
+    config::{Config, ConfigError, LimitConfig, TimeoutConfig},
+    cors,
+    digest::Digests,
+    etag::Etags,
+    error::ErrorPages,
+    events::{ChangeKind, Events},
+    files::FileBodies,
+    form_upload::FormUploads,
+    limits::{ConcurrencyLimits, OpenFiles},
+    listener::Connections,
+    metrics::Metrics,
+    mime_types::MimeTypes,
+    paths::PathResolver,
+    proxies::Proxies,
+    rate_limit::RateLimits,
+    response_headers::ResponseHeaders,
+    search::Search,
+    security_headers::SecurityHeaders,
+    stat_cache::StatCache,
+    thumbnails::Thumbnails,
+    timeouts::Deadlines,
+    tus::TusStore,
+    uploads::Uploads,
+};
+
+// Shared state available to every request handler
+#[derive(Debug)]
+pub struct AppState {
+    // Replaced when the configuration is reloaded
+    pub error_pages: Reloadable<ErrorPages>,
+    pub mime_types: Reloadable<MimeTypes>,
+    pub paths: Reloadable<PathResolver>,
+    pub tus: Option<TusStore>,
+    pub digests: Digests,
+    pub etags: Etags,
+    pub timeouts: TimeoutConfig,
+    pub deadlines: Deadlines,
+    pub limits: LimitConfig,
+    pub uploads: Uploads,
+    pub concurrency: ConcurrencyLimits,
+    pub open_files: OpenFiles,
+    pub rate_limits: RateLimits,
+    pub files: FileBodies,
+    pub stats: StatCache,
+    pub cache_control: Reloadable<CacheControl>,
+    pub response_headers: Reloadable<ResponseHeaders>,
+    pub access_log: Option<AccessLog>,
+    // Requests slower than this are logged
+    pub slow_request: Option<Duration>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub proxies: Proxies,
+    pub cors: Option<CorsLayer>,
+    pub security_headers: SecurityHeaders,
+    pub archives: Option<Archiver>,
+    pub thumbnails: Option<Thumbnails>,
+    pub form_uploads: Option<FormUploads>,
+    pub events: Option<Events>,
+    // Whether a filesystem watcher reports every change, ours included
+    pub watched: bool,
+    pub search: Option<Search>,
+    pub admin: Option<Admin>,
+    // Tracked only when the admin API can show them
+    pub connections: Option<Arc<Connections>>,
+    // Set through the admin API ahead of a restart: connections are closed
+    // after their current request and readiness probes fail
+    pub draining: AtomicBool,
+}
+
+impl AppState {
+    pub fn from_config(config: &Config) -> Result<AppState, ConfigError> {
+        let uploads = Uploads::from_config(&config.uploads)?;
+        let open_files = OpenFiles::new(config.runtime.max_open_files);
+        Ok(AppState {
+            error_pages: Reloadable::new(ErrorPages::from_config(&config.errors)?),
+            mime_types: Reloadable::new(MimeTypes::from_config(&config.mime)?),
+            paths: Reloadable::new(PathResolver::from_config(&config.paths)?),
+            tus: match config.tus.enabled {
+                true => Some(TusStore::from_config(&config.tus, &uploads)?),
+                false => None,
+            },
+            digests: Digests::from_config(&config.digest),
+            etags: Etags::from_config(&config.etag),
+            timeouts: config.timeouts.clone(),
+            deadlines: Deadlines::from_config(&config.timeouts)?,
+            limits: config.limits,
+            uploads,
+            concurrency: ConcurrencyLimits::from_config(&config.limits),
+            open_files: open_files.clone(),
+            rate_limits: RateLimits::from_config(&config.rate_limit),
+            files: FileBodies::from_config(&config.files),
+            stats: StatCache::from_config(&config.files),
+            cache_control: Reloadable::new(CacheControl::from_config(&config.cache_control)?),
+            response_headers: Reloadable::new(ResponseHeaders::from_config(&config.headers)?),
+            access_log: match config.access_log.enabled {
+                true => Some(AccessLog::from_config(&config.access_log, config.logging.format)?),
+                false => None,
+            },
+            slow_request: (config.logging.slow_request_ms > 0)
+                .then(|| Duration::from_millis(config.logging.slow_request_ms)),
+            metrics: config.metrics.enabled.then(Arc::default),
+            proxies: Proxies::from_config(&config.proxies)?,
+            cors: cors::layer(&config.cors)?,
+            security_headers: SecurityHeaders::from_config(&config.security_headers)?,
+            archives: config.archive.enabled
+                .then(|| Archiver::from_config(
+                    &config.archive, config.files.chunk_size, config.limits.max_walk_entries, open_files,
+                )),
+            form_uploads: config.form_upload.enabled
+                .then(|| FormUploads::from_config(&config.form_upload)),
+            thumbnails: match config.thumbnails.enabled {
+                true => Some(Thumbnails::from_config(&config.thumbnails)?),
+                false => None,
+            },
+            events: config.events.enabled.then(|| Events::from_config(&config.events)),
+            watched: config.watch.enabled,
+            search: config.search.enabled
+                .then(|| Search::from_config(&config.search, config.limits.max_walk_entries)),
+            admin: Admin::from_config(&config.admin, config),
+            connections: config.admin.enabled_token().is_some().then(Arc::default),
+            draining: AtomicBool::new(false),
+        })
+    }
+
+    // Record a change the server made to the served tree: drop cached
+    // copies and tell event subscribers, unless the watcher will
+    pub fn changed(&self, kind: ChangeKind, path: &Path) {
+        if self.watched {
+            self.invalidate(path);
+        } else {
+            self.observed(kind, path);
+        }
+    }
+
+    // Record a change seen on disk
+    pub fn observed(&self, kind: ChangeKind, path: &Path) {
+        self.invalidate(path);
+        if let Some(events) = &self.events {
+            events.publish(kind, path);
+        }
+    }
+
+    // Drop cached copies and metadata of a path
+    pub fn invalidate(&self, path: &Path) {
+        self.files.invalidate(path);
+        self.stats.invalidate(path);
+    }
+
+    // Apply the parts of a new configuration that can change at runtime.
+    // Nothing is replaced unless all of them are valid
+    pub fn reload(&self, config: &Config) -> Result<(), ConfigError> {
+        let error_pages = ErrorPages::from_config(&config.errors)?;
+        let mime_types = MimeTypes::from_config(&config.mime)?;
+        let paths = PathResolver::from_config(&config.paths)?;
+        let cache_control = CacheControl::from_config(&config.cache_control)?;
+        let response_headers = ResponseHeaders::from_config(&config.headers)?;
+
+        self.error_pages.set(error_pages);
+        self.mime_types.set(mime_types);
+        self.paths.set(paths);
+        self.cache_control.set(cache_control);
+        self.response_headers.set(response_headers);
+        if let Some(admin) = &self.admin {
+            admin.reload(config);
+        }
+        Ok(())
+    }
+}
+
+// A value requests read through `get`, swapped out as a whole on reload;
+// requests already holding the old value finish with it
+#[derive(Debug)]
+pub struct Reloadable<T>(RwLock<Arc<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Reloadable<T> {
+        Reloadable(RwLock::new(Arc::new(value)))
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
@@ -0,0 +1,24 @@
+use std::{
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+// Paths are resolved against the working directory, which all tests
+// share, so they all run in one temporary served directory and keep to
+// subdirectories of their own. Returns the emptied subdirectory `name`,
+// relative to the served directory
+pub fn scratch_dir(name: &str) -> PathBuf {
+    static SERVED: OnceLock<PathBuf> = OnceLock::new();
+    SERVED.get_or_init(|| {
+        let served = std::env::temp_dir().join(format!("axum-webdav-tests-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&served);
+        std::fs::create_dir_all(&served).unwrap();
+        std::env::set_current_dir(&served).unwrap();
+        served
+    });
+
+    let dir = PathBuf::from(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
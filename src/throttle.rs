@@ -0,0 +1,147 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::{self, Instant, Sleep};
+
+// Smallest amount worth waking up for, so a throttled connection moves
+// data in reasonable chunks instead of a byte at a time
+const MIN_GRANT: f64 = 1024.0;
+
+// Byte budget refilled at a fixed rate, shared by whatever it throttles
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    // A limiter allowing `bytes_per_sec`, or None for unlimited
+    pub fn new(bytes_per_sec: u64) -> Option<Arc<RateLimiter>> {
+        let rate = bytes_per_sec as f64;
+        let burst = rate.max(MIN_GRANT * 16.0);
+        (bytes_per_sec > 0).then(|| Arc::new(RateLimiter {
+            rate,
+            burst,
+            state: Mutex::new((burst, Instant::now())),
+        }))
+    }
+
+    // Bytes that may be transferred now, or how long until some may
+    fn available(&self) -> Result<usize, Duration> {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, updated) = &mut *state;
+        let now = Instant::now();
+        *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * self.rate).min(self.burst);
+        *updated = now;
+
+        let wanted = MIN_GRANT.min(self.burst);
+        if *tokens >= wanted {
+            Ok(*tokens as usize)
+        } else {
+            Err(Duration::from_secs_f64((wanted - *tokens) / self.rate))
+        }
+    }
+
+    // Charge bytes actually transferred; concurrent users may overdraw
+    // slightly, which just delays the next grant
+    fn consume(&self, bytes: usize) {
+        self.state.lock().unwrap().0 -= bytes as f64;
+    }
+}
+
+// Throttles one direction of a connection against a set of limiters
+pub struct Throttle {
+    limiters: Vec<Arc<RateLimiter>>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttle {
+    pub fn new(limiters: impl IntoIterator<Item = Option<Arc<RateLimiter>>>) -> Throttle {
+        Throttle {
+            limiters: limiters.into_iter().flatten().collect(),
+            sleep: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.limiters.is_empty()
+    }
+
+    // How many of `wanted` bytes may be transferred now
+    pub fn poll_allowance(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(sleep) = self.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.sleep = None;
+            }
+
+            let mut allowed = wanted;
+            let mut wait = Duration::ZERO;
+            for limiter in &self.limiters {
+                match limiter.available() {
+                    Ok(available) => allowed = allowed.min(available),
+                    Err(until) => wait = wait.max(until),
+                }
+            }
+            if wait.is_zero() {
+                return Poll::Ready(allowed);
+            }
+            self.sleep = Some(Box::pin(time::sleep(wait)));
+        }
+    }
+
+    pub fn consume(&self, bytes: usize) {
+        for limiter in &self.limiters {
+            limiter.consume(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Push `total` bytes through the throttle in transfers of up to 4 KiB
+    async fn transfer(throttle: &mut Throttle, total: usize) {
+        let mut sent = 0;
+        while sent < total {
+            let allowed = std::future::poll_fn(|cx| throttle.poll_allowance(cx, 4096)).await;
+            throttle.consume(allowed);
+            sent += allowed;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn transfers_keep_to_the_rate_after_a_burst() {
+        let mut throttle = Throttle::new([RateLimiter::new(16 * 1024)]);
+        let started = Instant::now();
+        // The first 16 KiB go out at once, the rest at 16 KiB a second
+        transfer(&mut throttle, 64 * 1024).await;
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_secs(3) && elapsed < Duration::from_millis(3100), "{:?}", elapsed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shared_limiters_split_the_rate() {
+        let shared = RateLimiter::new(16 * 1024);
+        let mut first = Throttle::new([shared.clone()]);
+        let mut second = Throttle::new([shared, None]);
+        let started = Instant::now();
+        tokio::join!(transfer(&mut first, 32 * 1024), transfer(&mut second, 32 * 1024));
+        assert!(started.elapsed() >= Duration::from_secs(3), "{:?}", started.elapsed());
+    }
+
+    #[test]
+    fn zero_means_unlimited() {
+        assert!(RateLimiter::new(0).is_none());
+        assert!(!Throttle::new([None, None]).is_active());
+    }
+}
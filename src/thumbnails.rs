@@ -0,0 +1,333 @@
+use axum::{
+    http::{header, StatusCode},
+    response::Response,
+};
+use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageFormat, ImageReader};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+use tokio::{fs, io::AsyncReadExt, sync::Semaphore};
+
+use crate::{
+    config::{ConfigError, ThumbnailConfig},
+    error::AppError,
+    stat_cache::Stat,
+    state::AppState,
+};
+
+// Smallest edge length accepted in ?thumb=
+const MIN_SIZE: u32 = 16;
+
+// Downscaled previews of images, generated on first request and kept on
+// disk until the cache outgrows its limit
+#[derive(Debug)]
+pub struct Thumbnails {
+    cache_dir: PathBuf,
+    // 0 means unlimited
+    cache_max_bytes: u64,
+    cache: Mutex<CacheIndex>,
+    // None when decoding is not limited
+    decodes: Option<Arc<Semaphore>>,
+    max_size: u32,
+    max_source_size: u64,
+    quality: u8,
+}
+
+impl Thumbnails {
+    pub fn from_config(config: &ThumbnailConfig) -> Result<Thumbnails, ConfigError> {
+        let cache_dir = config.cache_dir.clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("axum-webdav-thumbnails"));
+        std::fs::create_dir_all(&cache_dir)
+            .map_err(|err| ConfigError::Io(cache_dir.clone(), err))?;
+
+        // Pick up what earlier runs left, oldest first. Partial files may
+        // belong to another server writing them
+        let mut found = Vec::new();
+        let entries = std::fs::read_dir(&cache_dir)
+            .map_err(|err| ConfigError::Io(cache_dir.clone(), err))?;
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_file() && !name.ends_with(".partial") => {
+                    found.push((metadata.modified().ok(), name, metadata.len()));
+                }
+                _ => {}
+            }
+        }
+        found.sort();
+        let mut cache = CacheIndex::default();
+        for (_, name, size) in found {
+            cache.touch(&name, size);
+        }
+
+        let thumbnails = Thumbnails {
+            cache_dir,
+            cache_max_bytes: config.cache_max_bytes,
+            cache: Mutex::new(cache),
+            decodes: (config.max_decodes > 0).then(|| Arc::new(Semaphore::new(config.max_decodes))),
+            max_size: config.max_size,
+            max_source_size: config.max_source_size,
+            quality: config.quality,
+        };
+        for name in thumbnails.evict() {
+            let _ = std::fs::remove_file(thumbnails.cache_dir.join(name));
+        }
+        Ok(thumbnails)
+    }
+
+    pub fn cache_dir(&self) -> &Path {
+        &self.cache_dir
+    }
+
+    // The edge length asked for with ?thumb=N, if any
+    pub fn requested_size(&self, query: Option<&str>) -> Result<Option<u32>, AppError> {
+        let Some(value) = query.and_then(|query| {
+            query.split('&').find_map(|pair| pair.strip_prefix("thumb="))
+        }) else {
+            return Ok(None);
+        };
+        match value.parse::<u32>() {
+            Ok(size) if (MIN_SIZE..=self.max_size).contains(&size) => Ok(Some(size)),
+            _ => Err(AppError::Rejected(StatusCode::BAD_REQUEST, format!(
+                "thumb must be a size from {} to {}", MIN_SIZE, self.max_size,
+            ))),
+        }
+    }
+
+    // Path of the cached thumbnail of `path`, fitting a `size` square,
+    // generating it first if needed. Changing the image changes the key
+    pub async fn get(
+        &self,
+        mut file: fs::File,
+        path: &Path,
+        stat: &Stat,
+        size: u32,
+    ) -> Result<PathBuf, AppError> {
+        let key = cache_key(path, stat, size);
+        for extension in ["jpg", "png"] {
+            let name = format!("{}.{}", key, extension);
+            let cached = self.cache_dir.join(&name);
+            if let Ok(metadata) = fs::metadata(&cached).await {
+                self.cache.lock().unwrap().touch(&name, metadata.len());
+                return Ok(cached);
+            }
+        }
+
+        if stat.len > self.max_source_size {
+            return Err(AppError::Rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("{} is too large to make a thumbnail of", path.display()),
+            ));
+        }
+        // Images are held in memory whole while they are decoded, so only
+        // so many are at once
+        let _decoding = match &self.decodes {
+            Some(decodes) => decodes.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        let mut source = Vec::with_capacity(stat.len as usize);
+        (&mut file).take(stat.len).read_to_end(&mut source).await
+            .map_err(|err| AppError::from_io(err, path))?;
+
+        // Decoding and scaling are CPU-bound
+        let quality = self.quality;
+        let encoded = tokio::task::spawn_blocking(move || render(&source, size, quality)).await
+            .map_err(|err| AppError::Io(path.display().to_string(), std::io::Error::other(err)))?;
+        let Some((bytes, extension)) = encoded else {
+            return Err(AppError::Rejected(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("{} is not an image a thumbnail can be made of", path.display()),
+            ));
+        };
+
+        // Write under a temporary name so readers never see a partial file
+        let name = format!("{}.{}", key, extension);
+        let cached = self.cache_dir.join(&name);
+        let partial = self.cache_dir.join(format!("{}.{}.partial", key, uuid::Uuid::new_v4()));
+        let stored = async {
+            fs::write(&partial, &bytes).await?;
+            fs::rename(&partial, &cached).await
+        };
+        if let Err(err) = stored.await {
+            let _ = fs::remove_file(&partial).await;
+            return Err(AppError::from_io(err, &cached));
+        }
+
+        self.cache.lock().unwrap().touch(&name, bytes.len() as u64);
+        for name in self.evict() {
+            let _ = fs::remove_file(self.cache_dir.join(name)).await;
+        }
+        Ok(cached)
+    }
+
+    // File names to delete to bring the cache back within its limit
+    fn evict(&self) -> Vec<String> {
+        match self.cache_max_bytes {
+            0 => Vec::new(),
+            max_bytes => self.cache.lock().unwrap().evict(max_bytes),
+        }
+    }
+}
+
+// Sizes of the cached thumbnails, by file name, in the order they were
+// last used
+#[derive(Debug, Default)]
+struct CacheIndex {
+    bytes: u64,
+    // File name to size and last use
+    entries: HashMap<String, (u64, u64)>,
+    by_use: BTreeMap<u64, String>,
+    uses: u64,
+}
+
+impl CacheIndex {
+    // Record a use of `name`, `size` bytes long
+    fn touch(&mut self, name: &str, size: u64) {
+        self.uses += 1;
+        if let Some((old_size, used)) = self.entries.insert(name.to_string(), (size, self.uses)) {
+            self.by_use.remove(&used);
+            self.bytes -= old_size;
+        }
+        self.by_use.insert(self.uses, name.to_string());
+        self.bytes += size;
+    }
+
+    // Forget the least recently used entries until the rest fit in
+    // `max_bytes`, returning their names. The latest is kept even if it
+    // alone does not fit, since it is about to be served
+    fn evict(&mut self, max_bytes: u64) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.bytes > max_bytes && self.entries.len() > 1 {
+            let Some((_, name)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some((size, _)) = self.entries.remove(&name) {
+                self.bytes -= size;
+            }
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+// Decode, scale to fit a `size` square and encode; JPEG unless the image
+// has transparency, which needs PNG. None for content that is not a
+// supported image
+fn render(source: &[u8], size: u32, quality: u8) -> Option<(Vec<u8>, &'static str)> {
+    let image = ImageReader::new(Cursor::new(source)).with_guessed_format().ok()?.decode().ok()?;
+    let thumbnail = image.thumbnail(size, size);
+
+    let mut encoded = Vec::new();
+    if thumbnail.color().has_alpha() {
+        thumbnail.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png).ok()?;
+        return Some((encoded, "png"));
+    }
+    let rgb = DynamicImage::ImageRgb8(thumbnail.to_rgb8());
+    JpegEncoder::new_with_quality(&mut encoded, quality).encode_image(&rgb).ok()?;
+    Some((encoded, "jpg"))
+}
+
+fn cache_key(path: &Path, stat: &Stat, size: u32) -> String {
+    let modified = stat.modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos());
+    let mut hasher = Sha256::new();
+    hasher.update(path.to_string_lossy().as_bytes());
+    hasher.update(format!("\0{}\0{}\0{}", stat.len, modified, size).as_bytes());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Response carrying a cached thumbnail
+pub async fn response(state: &AppState, thumbnail: &Path) -> Result<Response, AppError> {
+    let file = fs::File::open(thumbnail).await
+        .map_err(|err| AppError::from_io(err, thumbnail))?;
+    let metadata = file.metadata().await
+        .map_err(|err| AppError::from_io(err, thumbnail))?;
+    let body = state.files.body(thumbnail, file, &Stat::from(&metadata)).await?;
+
+    Ok(Response::builder()
+        .header(header::CONTENT_LENGTH, metadata.len())
+        .header(header::CONTENT_TYPE, state.mime_types.get().content_type(thumbnail))
+        .body(body)
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn thumbnails(dir: &Path, cache_max_bytes: u64) -> Thumbnails {
+        let config = ThumbnailConfig {
+            enabled: true,
+            cache_dir: Some(dir.join("cache")),
+            cache_max_bytes,
+            ..ThumbnailConfig::default()
+        };
+        Thumbnails::from_config(&config).unwrap()
+    }
+
+    fn image(path: &Path, shade: u8) -> Stat {
+        image::RgbImage::from_pixel(64, 64, image::Rgb([shade, 0, 0])).save(path).unwrap();
+        Stat::from(&std::fs::metadata(path).unwrap())
+    }
+
+    fn cached(thumbnails: &Thumbnails) -> usize {
+        std::fs::read_dir(thumbnails.cache_dir()).unwrap().count()
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted_first() {
+        let mut cache = CacheIndex::default();
+        cache.touch("a", 10);
+        cache.touch("b", 10);
+        cache.touch("c", 10);
+        cache.touch("a", 10);
+        assert_eq!(cache.evict(20), ["b"]);
+        assert_eq!(cache.evict(10), ["c"]);
+        assert_eq!(cache.bytes, 10);
+    }
+
+    #[test]
+    fn the_latest_entry_is_kept_even_when_too_big() {
+        let mut cache = CacheIndex::default();
+        cache.touch("small", 10);
+        cache.touch("big", 100);
+        assert_eq!(cache.evict(50), ["small"]);
+        assert!(cache.evict(50).is_empty());
+    }
+
+    #[tokio::test]
+    async fn generated_thumbnails_are_evicted_past_the_limit() {
+        let dir = scratch_dir("thumbnails-evict");
+        let thumbnails = thumbnails(&dir, 1);
+        for shade in [0, 255] {
+            let path = dir.join(format!("{}.png", shade));
+            let stat = image(&path, shade);
+            let file = fs::File::open(&path).await.unwrap();
+            let thumbnail = thumbnails.get(file, &path, &stat, 32).await.unwrap();
+            assert!(thumbnail.exists());
+            assert_eq!(cached(&thumbnails), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn thumbnails_left_by_earlier_runs_count_towards_the_limit() {
+        let dir = scratch_dir("thumbnails-restart");
+        let path = dir.join("image.png");
+        let stat = image(&path, 128);
+        let first = thumbnails(&dir, 0);
+        let file = fs::File::open(&path).await.unwrap();
+        first.get(file, &path, &stat, 32).await.unwrap();
+        std::fs::write(first.cache_dir().join("other.jpg"), "older").unwrap();
+        assert_eq!(cached(&first), 2);
+
+        let restarted = thumbnails(&dir, 1);
+        assert_eq!(cached(&restarted), 1);
+    }
+}
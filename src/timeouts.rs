@@ -0,0 +1,157 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{boxed, Bytes, HttpBody},
+    extract::State,
+    http::{HeaderMap, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http_body::SizeHint;
+use tokio::time::{self, Duration, Instant, Sleep};
+
+use crate::{
+    config::{ConfigError, TimeoutConfig},
+    error::AppError,
+    state::AppState,
+};
+
+// Whole-request deadlines: request_secs, unless the method has its own
+#[derive(Debug)]
+pub struct Deadlines {
+    default: Option<Duration>,
+    methods: HashMap<Method, Option<Duration>>,
+}
+
+impl Deadlines {
+    pub fn from_config(config: &TimeoutConfig) -> Result<Deadlines, ConfigError> {
+        let methods = config.methods.iter()
+            .map(|(method, secs)| {
+                let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .map_err(|_| ConfigError::Invalid(format!("invalid method in timeouts.methods: {:?}", method)))?;
+                Ok((method, (*secs > 0).then(|| Duration::from_secs(*secs))))
+            })
+            .collect::<Result<_, ConfigError>>()?;
+        Ok(Deadlines { default: config.request(), methods })
+    }
+
+    fn for_method(&self, method: &Method) -> Option<Duration> {
+        self.methods.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+// Middleware enforcing the optional whole-request deadline for the
+// request's method, covering both the handler and the streaming of its
+// response body. A handler still running then is answered with 504, as
+// the server rather than the client was too slow
+pub async fn deadline<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(limit) = state.deadlines.for_method(req.method()) else {
+        return next.run(req).await;
+    };
+
+    let deadline = Instant::now() + limit;
+    match time::timeout_at(deadline, next.run(req)).await {
+        Ok(response) => response.map(|body| boxed(DeadlineBody {
+            inner: body,
+            sleep: Box::pin(time::sleep_until(deadline)),
+        })),
+        Err(_) => AppError::Rejected(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!("Request took longer than {} seconds", limit.as_secs()),
+        ).into_response(),
+    }
+}
+
+// Response body that fails once the request deadline passes, which makes
+// hyper abort the connection
+struct DeadlineBody<B> {
+    inner: B,
+    sleep: Pin<Box<Sleep>>,
+}
+
+impl<B> HttpBody for DeadlineBody<B>
+where
+    B: HttpBody<Data = Bytes, Error = axum::Error> + Unpin,
+{
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        if self.sleep.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Some(Err(axum::Error::new("request deadline exceeded"))));
+        }
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    // A handler taking a minute, under a 10 second deadline for all but PUT
+    fn app() -> Router {
+        let state = AppState::from_config(&Config {
+            timeouts: TimeoutConfig {
+                request_secs: 10,
+                methods: [("put".to_string(), 0)].into(),
+                ..TimeoutConfig::default()
+            },
+            ..Config::default()
+        }).unwrap();
+        let slow = || async {
+            time::sleep(Duration::from_secs(60)).await;
+            "done"
+        };
+        Router::new()
+            .route("/slow", get(slow).put(slow))
+            .layer(middleware::from_fn_with_state(Arc::new(state), deadline))
+    }
+
+    async fn status(method: Method) -> StatusCode {
+        let req = Request::builder().method(method).uri("/slow").body(Body::empty()).unwrap();
+        app().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_handlers_time_out() {
+        let started = Instant::now();
+        assert_eq!(status(Method::GET).await, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn methods_can_have_no_deadline() {
+        assert_eq!(status(Method::PUT).await, StatusCode::OK);
+    }
+}
@@ -0,0 +1,205 @@
+use axum::{
+    body::{boxed, Bytes, HttpBody},
+    extract::State,
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use http_body::SizeHint;
+use percent_encoding::percent_decode_str;
+use std::{
+    future::Future,
+    io,
+    net::IpAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::{proxies::ClientIp, request_id::RequestId, state::AppState};
+
+tokio::task_local! {
+    // Timings of the request the current task is handling
+    static TIMINGS: Arc<Timings>;
+}
+
+// Time one request spent waiting on the filesystem
+#[derive(Debug, Default)]
+pub struct Timings {
+    fs_wait_us: AtomicU64,
+}
+
+impl Timings {
+    fn add(&self, waited: Duration) {
+        self.fs_wait_us.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn fs_wait(&self) -> Duration {
+        Duration::from_micros(self.fs_wait_us.load(Ordering::Relaxed))
+    }
+}
+
+// Run a filesystem operation, counting the time it takes towards the
+// current request's filesystem wait
+pub async fn fs_wait<F: Future>(operation: F) -> F::Output {
+    let started = Instant::now();
+    let output = operation.await;
+    let _ = TIMINGS.try_with(|timings| timings.add(started.elapsed()));
+    output
+}
+
+// Reader counting how long its reads wait towards the filesystem wait of
+// the request it was made for. Bodies are read after the handler returned,
+// from another task, so it keeps the timings itself
+pub struct TimedReader<R> {
+    inner: R,
+    timings: Option<Arc<Timings>>,
+    // When the read in progress started waiting
+    waiting: Option<Instant>,
+}
+
+impl<R> TimedReader<R> {
+    pub fn new(inner: R) -> TimedReader<R> {
+        TimedReader {
+            inner,
+            timings: TIMINGS.try_with(Arc::clone).ok(),
+            waiting: None,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TimedReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let started = *self.waiting.get_or_insert_with(Instant::now);
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if poll.is_ready() {
+            self.waiting = None;
+            if let Some(timings) = &self.timings {
+                timings.add(started.elapsed());
+            }
+        }
+        poll
+    }
+}
+
+// Middleware warning about requests slower than the configured threshold,
+// with where the time went: producing the response (handling), sending its
+// body (transfer), and within both, waiting on the filesystem
+pub async fn log_slow_requests<B>(
+    State(state): State<Arc<AppState>>,
+    Extension(ClientIp(remote)): Extension<ClientIp>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(threshold) = state.slow_request else {
+        return next.run(req).await;
+    };
+
+    let request = SlowRequest {
+        threshold,
+        started: Instant::now(),
+        handled: Duration::ZERO,
+        timings: Arc::default(),
+        method: req.method().to_string(),
+        path: percent_decode_str(req.uri().path()).decode_utf8_lossy().into_owned(),
+        remote,
+        request_id: req.extensions().get::<RequestId>().map(|id| id.0.clone()),
+        status: 0,
+    };
+    let timings = request.timings.clone();
+    let response = TIMINGS.scope(timings, next.run(req)).await;
+
+    let request = SlowRequest {
+        handled: request.started.elapsed(),
+        status: response.status().as_u16(),
+        ..request
+    };
+    response.map(|body| boxed(TimedBody { inner: body, request }))
+}
+
+// What is reported about a request, should it turn out slow
+struct SlowRequest {
+    threshold: Duration,
+    started: Instant,
+    // Time until the response head was ready
+    handled: Duration,
+    timings: Arc<Timings>,
+    method: String,
+    path: String,
+    remote: IpAddr,
+    request_id: Option<String>,
+    status: u16,
+}
+
+impl SlowRequest {
+    fn report(&self) {
+        let total = self.started.elapsed();
+        if total < self.threshold {
+            return;
+        }
+        tracing::warn!(
+            method = %self.method,
+            path = %self.path,
+            remote = %self.remote,
+            request_id = self.request_id.as_deref().unwrap_or("-"),
+            status = self.status,
+            total_ms = total.as_millis() as u64,
+            handling_ms = self.handled.as_millis() as u64,
+            transfer_ms = total.saturating_sub(self.handled).as_millis() as u64,
+            fs_wait_ms = self.timings.fs_wait().as_millis() as u64,
+            "Slow request",
+        );
+    }
+}
+
+// Response body reporting the request once it is done, completed or not
+struct TimedBody<B> {
+    inner: B,
+    request: SlowRequest,
+}
+
+impl<B> Drop for TimedBody<B> {
+    fn drop(&mut self) {
+        self.request.report();
+    }
+}
+
+impl<B> HttpBody for TimedBody<B>
+where
+    B: HttpBody<Data = Bytes, Error = axum::Error> + Unpin,
+{
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, axum::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, axum::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
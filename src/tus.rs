@@ -0,0 +1,673 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::SeekFrom,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{BodyStream, Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{head, post},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::digest::DynDigest;
+use tokio::{
+    fs,
+    io::AsyncSeekExt,
+};
+use tower_http::set_header::SetResponseHeaderLayer;
+use uuid::Uuid;
+
+use crate::{
+    config::{ConfigError, TusConfig},
+    digest::Algorithm,
+    error::{self, AppError},
+    events::ChangeKind,
+    paths::{self, PathResolver},
+    uploads::{self, UploadWriter, Uploads},
+    state::AppState,
+};
+
+// Resumable uploads following the tus 1.0.0 protocol (https://tus.io),
+// with the creation, checksum and termination extensions
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,checksum,termination";
+const CHECKSUM_ALGORITHMS: &str = "md5,sha1,sha256";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const TUS_CHECKSUM_ALGORITHM: HeaderName = HeaderName::from_static("tus-checksum-algorithm");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_CHECKSUM: HeaderName = HeaderName::from_static("upload-checksum");
+
+// Staging area for uploads in progress; each upload is a data file plus a
+// JSON file recording where it goes, so uploads survive restarts
+#[derive(Debug)]
+pub struct TusStore {
+    staging: PathBuf,
+    max_size: Option<u64>,
+    // Uploads currently receiving a PATCH
+    active: Mutex<HashSet<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UploadInfo {
+    target: PathBuf,
+    length: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadSummary {
+    id: String,
+    target: PathBuf,
+    length: u64,
+    offset: u64,
+    // Currently receiving a PATCH
+    active: bool,
+}
+
+// Marks an upload busy until dropped
+struct ActiveUpload<'a> {
+    store: &'a TusStore,
+    id: String,
+}
+
+impl Drop for ActiveUpload<'_> {
+    fn drop(&mut self) {
+        self.store.active.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl TusStore {
+    pub fn from_config(config: &TusConfig, uploads: &Uploads) -> Result<TusStore, ConfigError> {
+        // Uploads outlive requests, so without a staging directory of
+        // their own they go to the system's temporary one, and finished
+        // ones are copied over if that is on another filesystem
+        let staging = uploads.staging_dir.as_ref()
+            .map_or_else(|| std::env::temp_dir().join("axum-webdav-tus"), |dir| dir.join("tus"));
+        std::fs::create_dir_all(&staging)
+            .map_err(|err| ConfigError::Io(staging.clone(), err))?;
+
+        Ok(TusStore {
+            staging,
+            max_size: config.max_size,
+            active: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub fn staging_dir(&self) -> &std::path::Path {
+        &self.staging
+    }
+
+    fn data_path(&self, id: &str) -> PathBuf {
+        self.staging.join(format!("{}.bin", id))
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.staging.join(format!("{}.json", id))
+    }
+
+    fn acquire(&self, id: &str) -> Result<ActiveUpload<'_>, AppError> {
+        if !self.active.lock().unwrap().insert(id.to_string()) {
+            return Err(AppError::Locked(format!("upload {}", id)));
+        }
+        Ok(ActiveUpload { store: self, id: id.to_string() })
+    }
+
+    // Every upload in the staging area, for inspection
+    pub async fn uploads(&self) -> Result<Vec<UploadSummary>, AppError> {
+        let mut entries = fs::read_dir(&self.staging).await
+            .map_err(|err| AppError::from_io(err, &self.staging))?;
+
+        let mut uploads = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            // Uploads finishing or terminated meanwhile are skipped
+            if let Ok((info, offset)) = self.load(id).await {
+                uploads.push(UploadSummary {
+                    id: id.to_string(),
+                    target: info.target,
+                    length: info.length,
+                    offset,
+                    active: self.active.lock().unwrap().contains(id),
+                });
+            }
+        }
+        Ok(uploads)
+    }
+
+    // Read an upload's info and current offset
+    async fn load(&self, id: &str) -> Result<(UploadInfo, u64), AppError> {
+        let not_found = || AppError::NotFound(format!("upload {}", id));
+
+        // Only ids we minted can name files in the staging directory
+        if Uuid::try_parse(id).is_err() {
+            return Err(not_found());
+        }
+
+        let info = fs::read(self.info_path(id)).await.map_err(|_| not_found())?;
+        let info: UploadInfo = serde_json::from_slice(&info).map_err(|_| not_found())?;
+        let offset = fs::metadata(self.data_path(id)).await.map_err(|_| not_found())?.len();
+
+        Ok((info, offset))
+    }
+
+    // Move a complete upload to its target in the served directory. The
+    // target is checked against the path policy again, which may have
+    // changed, or had a symlink put in its way, since the upload started
+    async fn finish(&self, paths: &PathResolver, id: &str, info: &UploadInfo) -> Result<(), AppError> {
+        let target = paths.resolve_new(&paths::glob_path(&info.target)).await?;
+        uploads::move_into_place(&self.data_path(id), &target).await?;
+        let _ = fs::remove_file(self.info_path(id)).await;
+        Ok(())
+    }
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/.tus", post(create)
+            .options(describe)
+            .fallback(error::method_not_allowed("POST, OPTIONS")))
+        .route("/.tus/:id", head(offset)
+            .patch(append)
+            .delete(terminate)
+            .options(describe)
+            .fallback(error::method_not_allowed("HEAD, PATCH, DELETE, OPTIONS")))
+        .layer(SetResponseHeaderLayer::overriding(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION)))
+        .layer(SetResponseHeaderLayer::overriding(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION)))
+}
+
+fn store(state: &AppState) -> Result<&TusStore, AppError> {
+    state.tus.as_ref().ok_or_else(|| AppError::NotFound(".tus".into()))
+}
+
+// Every request but OPTIONS must speak our protocol version
+fn check_version(headers: &HeaderMap) -> Result<(), AppError> {
+    match headers.get(TUS_RESUMABLE) {
+        Some(version) if version == TUS_VERSION => Ok(()),
+        _ => Err(AppError::PreconditionFailed(format!("Tus-Resumable must be {}", TUS_VERSION))),
+    }
+}
+
+fn header_u64(headers: &HeaderMap, name: &HeaderName) -> Result<Option<u64>, AppError> {
+    let Some(value) = headers.get(name) else {
+        return Ok(None);
+    };
+    value.to_str().ok()
+        .and_then(|value| value.parse().ok())
+        .map(Some)
+        .ok_or_else(|| AppError::Rejected(StatusCode::BAD_REQUEST, format!("Invalid {} header", name)))
+}
+
+// Upload-Metadata is "key base64value,key base64value,..."
+fn parse_metadata(headers: &HeaderMap) -> Result<HashMap<String, String>, AppError> {
+    let invalid = || AppError::Rejected(StatusCode::BAD_REQUEST, "Invalid Upload-Metadata header".into());
+
+    let Some(value) = headers.get(UPLOAD_METADATA) else {
+        return Ok(HashMap::new());
+    };
+    let value = value.to_str().map_err(|_| invalid())?;
+
+    let mut metadata = HashMap::new();
+    for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (key, encoded) = pair.split_once(' ').unwrap_or((pair, ""));
+        let decoded = STANDARD.decode(encoded.trim()).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        metadata.insert(key.to_string(), decoded);
+    }
+    Ok(metadata)
+}
+
+// Digest expected for the body of a PATCH
+struct Checksum {
+    hasher: Box<dyn DynDigest + Send>,
+    expected: Vec<u8>,
+}
+
+// Upload-Checksum is "<algorithm> <base64 digest>"
+fn parse_checksum(headers: &HeaderMap) -> Result<Option<Checksum>, AppError> {
+    let invalid = || AppError::Rejected(StatusCode::BAD_REQUEST, "Invalid Upload-Checksum header".into());
+
+    let Some(value) = headers.get(UPLOAD_CHECKSUM) else {
+        return Ok(None);
+    };
+    let value = value.to_str().map_err(|_| invalid())?;
+    let (algorithm, encoded) = value.split_once(' ').ok_or_else(invalid)?;
+    let expected = STANDARD.decode(encoded.trim()).map_err(|_| invalid())?;
+
+    let algorithm = match algorithm {
+        "md5" => Algorithm::Md5,
+        "sha1" => Algorithm::Sha1,
+        "sha256" => Algorithm::Sha256,
+        _ => return Err(AppError::Rejected(
+            StatusCode::BAD_REQUEST,
+            format!("Unsupported checksum algorithm {:?}", algorithm),
+        )),
+    };
+    Ok(Some(Checksum { hasher: algorithm.hasher(), expected }))
+}
+
+async fn describe(State(state): State<Arc<AppState>>) -> Result<Response, AppError> {
+    let store = store(&state)?;
+
+    let mut response = (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_EXTENSION, TUS_EXTENSIONS),
+            (TUS_CHECKSUM_ALGORITHM, CHECKSUM_ALGORITHMS),
+        ],
+    ).into_response();
+    if let Some(max_size) = store.max_size {
+        response.headers_mut().insert(TUS_MAX_SIZE, max_size.into());
+    }
+    Ok(response)
+}
+
+async fn create(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let store = store(&state)?;
+    check_version(&headers)?;
+
+    let length = header_u64(&headers, &UPLOAD_LENGTH)?
+        .ok_or_else(|| AppError::Rejected(StatusCode::BAD_REQUEST, "Upload-Length is required".into()))?;
+    if let Some(max_size) = store.max_size.filter(|max_size| length > *max_size) {
+        return Err(AppError::Rejected(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload exceeds the {} byte limit", max_size),
+        ));
+    }
+
+    // Clients name the destination with a "path" (or plain "filename") entry
+    let metadata = parse_metadata(&headers)?;
+    let target = metadata.get("path").or_else(|| metadata.get("filename"))
+        .ok_or_else(|| AppError::Rejected(
+            StatusCode::BAD_REQUEST,
+            "Upload-Metadata must include a path or filename".into(),
+        ))?;
+    let target = state.paths.get().resolve_new(target.trim_start_matches('/')).await?;
+    if fs::symlink_metadata(&target).await.is_ok() {
+        return Err(AppError::Conflict(target.display().to_string()));
+    }
+    state.uploads.check_space(store.staging_dir(), length, &target).await?;
+
+    let id = Uuid::new_v4().to_string();
+    let info = UploadInfo { target, length };
+    let _handle = state.open_files.reserve().await;
+    let data = fs::File::create(store.data_path(&id)).await
+        .map_err(|err| AppError::from_io(err, &store.data_path(&id)))?;
+    // Holes would be filled by reserving space
+    if state.uploads.preallocate && !state.uploads.sparse {
+        if let Err(err) = uploads::preallocate(&data, length).await {
+            let _ = fs::remove_file(store.data_path(&id)).await;
+            return Err(AppError::from_io(err, &info.target));
+        }
+    }
+    fs::write(store.info_path(&id), serde_json::to_vec(&info).unwrap()).await
+        .map_err(|err| AppError::from_io(err, &store.info_path(&id)))?;
+
+    if length == 0 {
+        store.finish(&state.paths.get(), &id, &info).await?;
+        state.changed(ChangeKind::Create, &info.target);
+    }
+
+    Ok((StatusCode::CREATED, [(header::LOCATION, format!("/.tus/{}", id))]).into_response())
+}
+
+async fn offset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let store = store(&state)?;
+    check_version(&headers)?;
+
+    let (info, offset) = store.load(&id).await?;
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_LENGTH, info.length.to_string()),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    ).into_response())
+}
+
+async fn append(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+) -> Result<Response, AppError> {
+    let store = store(&state)?;
+    check_version(&headers)?;
+
+    if headers.get(header::CONTENT_TYPE).is_none_or(|value| value != OFFSET_CONTENT_TYPE) {
+        return Err(AppError::Rejected(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {}", OFFSET_CONTENT_TYPE),
+        ));
+    }
+    let offset = header_u64(&headers, &UPLOAD_OFFSET)?
+        .ok_or_else(|| AppError::Rejected(StatusCode::BAD_REQUEST, "Upload-Offset is required".into()))?;
+    let mut checksum = parse_checksum(&headers)?;
+
+    let _active = store.acquire(&id)?;
+    let (info, current) = store.load(&id).await?;
+    if offset != current {
+        return Err(AppError::Rejected(
+            StatusCode::CONFLICT,
+            format!("Upload-Offset {} does not match the current offset {}", offset, current),
+        ));
+    }
+
+    // Everything above is decided before the body is first polled, which is
+    // when hyper answers "Expect: 100-continue"; a body announced as too
+    // long is refused here too, so the client never starts sending it
+    if header_u64(&headers, &header::CONTENT_LENGTH)?
+        .is_some_and(|length| offset + length > info.length)
+    {
+        return Err(AppError::Rejected(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Upload exceeds its declared length of {} bytes", info.length),
+        ));
+    }
+
+    let data = store.data_path(&id);
+    let _handle = state.open_files.reserve().await;
+    let mut file = fs::OpenOptions::new().write(true).open(&data).await
+        .map_err(|err| AppError::from_io(err, &data))?;
+    file.seek(SeekFrom::Start(offset)).await
+        .map_err(|err| AppError::from_io(err, &data))?;
+
+    let mut writer = UploadWriter::new(&mut file, offset, state.uploads.sparse);
+    let mut written = offset;
+    let mut result = Ok(());
+    loop {
+        // Give up on clients that stop sending mid-body
+        let next = match state.timeouts.body_read() {
+            Some(idle) => match tokio::time::timeout(idle, body.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    result = Err(AppError::Rejected(StatusCode::REQUEST_TIMEOUT, "Upload body stalled".into()));
+                    break;
+                }
+            },
+            None => body.next().await,
+        };
+        let Some(chunk) = next else {
+            break;
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                // What arrived is kept for the client to resume from
+                if error::is_disconnect(&err) {
+                    tracing::info!("Upload {} aborted by the client at offset {}", id, written);
+                }
+                result = Err(AppError::Rejected(StatusCode::BAD_REQUEST, "Upload body was interrupted".into()));
+                break;
+            }
+        };
+        if written + chunk.len() as u64 > info.length {
+            result = Err(AppError::Rejected(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Upload exceeds its declared length of {} bytes", info.length),
+            ));
+            break;
+        }
+        if let Err(err) = writer.write(&chunk).await {
+            result = Err(AppError::from_io(err, &data));
+            break;
+        }
+        if let Some(checksum) = checksum.as_mut() {
+            checksum.hasher.update(&chunk);
+        }
+        written += chunk.len() as u64;
+    }
+    if let Err(err) = writer.finish().await {
+        result = result.and(Err(AppError::from_io(err, &data)));
+    }
+
+    // A checksummed chunk is kept only if it arrived whole and intact
+    if let Some(checksum) = checksum {
+        let intact = result.is_ok()
+            && checksum.hasher.finalize().as_ref() == checksum.expected.as_slice();
+        if !intact {
+            file.set_len(offset).await
+                .map_err(|err| AppError::from_io(err, &data))?;
+            result?;
+            return Err(AppError::Rejected(
+                StatusCode::from_u16(460).unwrap(),
+                "Checksum mismatch".into(),
+            ));
+        }
+    }
+    result?;
+
+    if written == info.length {
+        store.finish(&state.paths.get(), &id, &info).await?;
+        state.changed(ChangeKind::Create, &info.target);
+    }
+
+    Ok((StatusCode::NO_CONTENT, [(UPLOAD_OFFSET, written.to_string())]).into_response())
+}
+
+async fn terminate(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let store = store(&state)?;
+    check_version(&headers)?;
+
+    let _active = store.acquire(&id)?;
+    store.load(&id).await?;
+    let _ = fs::remove_file(store.data_path(&id)).await;
+    let _ = fs::remove_file(store.info_path(&id)).await;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::{Config, PathConfig, UploadConfig}, testing::scratch_dir};
+    use axum::{body::Body, http::{Method, Request}};
+    use tower::ServiceExt;
+
+    fn store(dir: &std::path::Path) -> TusStore {
+        let uploads = UploadConfig { staging_dir: Some(dir.join("staging")), ..UploadConfig::default() };
+        TusStore::from_config(&TusConfig::default(), &Uploads::from_config(&uploads).unwrap()).unwrap()
+    }
+
+    fn resolver(deny: &[&str]) -> PathResolver {
+        PathResolver::from_config(&PathConfig {
+            deny: deny.iter().map(|glob| glob.to_string()).collect(),
+            ..PathConfig::default()
+        }).unwrap()
+    }
+
+    // Stage a complete upload of `data` bound for `target`
+    fn stage(store: &TusStore, target: PathBuf, data: &str) -> (String, UploadInfo) {
+        let id = Uuid::new_v4().to_string();
+        let info = UploadInfo { target, length: data.len() as u64 };
+        std::fs::write(store.data_path(&id), data).unwrap();
+        std::fs::write(store.info_path(&id), serde_json::to_vec(&info).unwrap()).unwrap();
+        (id, info)
+    }
+
+    #[tokio::test]
+    async fn finish_moves_the_upload_to_its_target() {
+        let dir = scratch_dir("tus-finish");
+        let store = store(&dir);
+        let (id, info) = stage(&store, dir.join("file.txt"), "hello");
+
+        store.finish(&resolver(&[]), &id, &info).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("file.txt")).unwrap(), "hello");
+        assert!(store.load(&id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn finish_keeps_a_file_created_meanwhile() {
+        let dir = scratch_dir("tus-finish-conflict");
+        let store = store(&dir);
+        let (id, info) = stage(&store, dir.join("file.txt"), "upload");
+        std::fs::write(dir.join("file.txt"), "existing").unwrap();
+
+        let finished = store.finish(&resolver(&[]), &id, &info).await;
+        assert!(matches!(finished, Err(AppError::Conflict(_))));
+        assert_eq!(std::fs::read_to_string(dir.join("file.txt")).unwrap(), "existing");
+    }
+
+    #[tokio::test]
+    async fn finish_applies_the_current_path_policy() {
+        let dir = scratch_dir("tus-finish-denied");
+        let store = store(&dir);
+        let (id, info) = stage(&store, dir.join("secret.key"), "upload");
+
+        let finished = store.finish(&resolver(&["**/*.key"]), &id, &info).await;
+        assert!(matches!(finished, Err(AppError::NotFound(_))));
+        assert!(!dir.join("secret.key").exists());
+        assert_eq!(store.load(&id).await.unwrap().1, 6);
+    }
+
+    // The tus routes, staging under `dir`
+    fn app(dir: &std::path::Path) -> Router {
+        app_with(UploadConfig { staging_dir: Some(dir.join("staging")), ..UploadConfig::default() })
+    }
+
+    fn app_with(uploads: UploadConfig) -> Router {
+        let config = Config {
+            tus: TusConfig { enabled: true, ..TusConfig::default() },
+            uploads,
+            ..Config::default()
+        };
+        routes().with_state(Arc::new(AppState::from_config(&config).unwrap()))
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, headers: &[(&str, &str)], body: &str) -> Response {
+        let mut req = Request::builder().method(method).uri(uri).header(TUS_RESUMABLE, TUS_VERSION);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        app.clone().oneshot(req.body(Body::from(body.to_string())).unwrap()).await.unwrap()
+    }
+
+    fn header_text<'a>(response: &'a Response, name: &HeaderName) -> &'a str {
+        response.headers().get(name).unwrap().to_str().unwrap()
+    }
+
+    // Create an upload of `length` bytes bound for `target`, returning its URL
+    async fn create_upload(app: &Router, target: &str, length: usize) -> String {
+        let metadata = format!("path {}", STANDARD.encode(target));
+        let length = length.to_string();
+        let headers = [("upload-length", length.as_str()), ("upload-metadata", metadata.as_str())];
+        let response = send(app, Method::POST, "/.tus", &headers, "").await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        header_text(&response, &header::LOCATION).to_string()
+    }
+
+    async fn patch(app: &Router, url: &str, offset: u64, checksum: Option<&str>, data: &str) -> Response {
+        let offset = offset.to_string();
+        let mut headers = vec![("content-type", OFFSET_CONTENT_TYPE), ("upload-offset", offset.as_str())];
+        if let Some(checksum) = checksum {
+            headers.push(("upload-checksum", checksum));
+        }
+        send(app, Method::PATCH, url, &headers, data).await
+    }
+
+    async fn current_offset(app: &Router, url: &str) -> Option<u64> {
+        let response = send(app, Method::HEAD, url, &[], "").await;
+        (response.status() == StatusCode::OK).then(|| header_text(&response, &UPLOAD_OFFSET).parse().unwrap())
+    }
+
+    fn sha256(data: &str) -> String {
+        let mut hasher = Algorithm::Sha256.hasher();
+        hasher.update(data.as_bytes());
+        format!("sha256 {}", STANDARD.encode(hasher.finalize()))
+    }
+
+    #[tokio::test]
+    async fn uploads_resume_from_the_current_offset() {
+        let dir = scratch_dir("tus-resume");
+        let app = app(&dir);
+        let url = create_upload(&app, "tus-resume/file.txt", 5).await;
+        assert_eq!(current_offset(&app, &url).await, Some(0));
+
+        let response = patch(&app, &url, 0, None, "hel").await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header_text(&response, &UPLOAD_OFFSET), "3");
+        assert_eq!(patch(&app, &url, 0, None, "lo").await.status(), StatusCode::CONFLICT);
+        assert_eq!(current_offset(&app, &url).await, Some(3));
+
+        assert_eq!(patch(&app, &url, 3, None, "lo").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(std::fs::read_to_string(dir.join("file.txt")).unwrap(), "hello");
+        assert_eq!(current_offset(&app, &url).await, None);
+    }
+
+    #[tokio::test]
+    async fn chunks_failing_their_checksum_are_discarded() {
+        let dir = scratch_dir("tus-checksum");
+        let app = app(&dir);
+        let url = create_upload(&app, "tus-checksum/file.txt", 5).await;
+
+        let response = patch(&app, &url, 0, Some(&sha256("other")), "hel").await;
+        assert_eq!(response.status().as_u16(), 460);
+        assert_eq!(current_offset(&app, &url).await, Some(0));
+
+        assert_eq!(patch(&app, &url, 0, Some(&sha256("hel")), "hel").await.status(), StatusCode::NO_CONTENT);
+        assert_eq!(current_offset(&app, &url).await, Some(3));
+        let invalid = patch(&app, &url, 3, Some("crc32 AAAA"), "lo").await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn uploads_cannot_outgrow_their_length() {
+        let dir = scratch_dir("tus-length");
+        let app = app(&dir);
+        let url = create_upload(&app, "tus-length/file.txt", 3).await;
+
+        assert_eq!(patch(&app, &url, 0, None, "toolong").await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!dir.join("file.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn requests_must_speak_the_protocol_version() {
+        let dir = scratch_dir("tus-version");
+        let app = app(&dir);
+        let req = Request::post("/.tus").header("upload-length", "1").body(Body::empty()).unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn uploads_must_fit_with_the_reserve_left_free() {
+        let dir = scratch_dir("tus-space");
+        let app = app_with(UploadConfig {
+            staging_dir: Some(dir.join("staging")),
+            reserve_bytes: u64::MAX / 2,
+            ..UploadConfig::default()
+        });
+
+        let metadata = format!("path {}", STANDARD.encode("tus-space/file.txt"));
+        let headers = [("upload-length", "1"), ("upload-metadata", metadata.as_str())];
+        let response = send(&app, Method::POST, "/.tus", &headers, "").await;
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+        assert_eq!(std::fs::read_dir(dir.join("staging/tus")).unwrap().count(), 0);
+    }
+}
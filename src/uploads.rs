@@ -0,0 +1,343 @@
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+
+use uuid::Uuid;
+
+use crate::{
+    config::{ConfigError, UploadConfig},
+    error::AppError,
+};
+
+// Name prefix of files still being written
+pub const PARTIAL_PREFIX: &str = ".upload-";
+
+pub fn is_partial(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name.to_string_lossy().starts_with(PARTIAL_PREFIX))
+}
+
+// How uploads of any kind are written
+#[derive(Debug)]
+pub struct Uploads {
+    pub sparse: bool,
+    pub preallocate: bool,
+    // Where uploads in progress are kept, on the served directory's
+    // filesystem; next to their targets when not set
+    pub staging_dir: Option<PathBuf>,
+    // Bytes to leave free on the disk
+    reserve: u64,
+}
+
+impl Uploads {
+    pub fn from_config(config: &UploadConfig) -> Result<Uploads, ConfigError> {
+        if let Some(dir) = &config.staging_dir {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| ConfigError::Io(dir.clone(), err))?;
+            check_same_filesystem(dir)?;
+        }
+        Ok(Uploads {
+            sparse: config.sparse,
+            preallocate: config.preallocate,
+            staging_dir: config.staging_dir.clone(),
+            reserve: config.reserve_bytes,
+        })
+    }
+
+    // Fail with 507 unless an upload of `length` bytes to `target` fits on
+    // the filesystem of `dir`, where it is written, with the reserve left
+    // free. Filesystems that cannot tell are assumed to have room
+    pub async fn check_space(&self, dir: &Path, length: u64, target: &Path) -> Result<(), AppError> {
+        let Ok(available) = available_space(dir).await else {
+            return Ok(());
+        };
+        if length.saturating_add(self.reserve) > available {
+            return Err(AppError::InsufficientStorage(target.display().to_string()));
+        }
+        Ok(())
+    }
+
+    // Temporary name for an upload to `target` while it is written
+    pub fn partial_path(&self, target: &Path, name: &str) -> PathBuf {
+        match &self.staging_dir {
+            Some(dir) => dir.join(name),
+            None => target.with_file_name(name),
+        }
+    }
+}
+
+// Move a finished upload to `target`, failing with 409 rather than
+// replacing whatever appeared there since the upload started. From another
+// filesystem, the data is copied to a partial file next to the target
+// first, so readers never see the target half written
+pub async fn move_into_place(source: &Path, target: &Path) -> Result<(), AppError> {
+    let conflict = |err: io::Error| match err.kind() {
+        io::ErrorKind::AlreadyExists => AppError::Conflict(target.display().to_string()),
+        _ => AppError::from_io(err, target),
+    };
+    match rename_no_replace(source, target).await {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {}
+        result => return result.map_err(conflict),
+    }
+
+    let partial = target.with_file_name(format!("{}{}", PARTIAL_PREFIX, Uuid::new_v4()));
+    let copied = match fs::copy(source, &partial).await {
+        Ok(_) => rename_no_replace(&partial, target).await.map_err(conflict),
+        Err(err) => Err(AppError::from_io(err, target)),
+    };
+    if copied.is_err() {
+        let _ = fs::remove_file(&partial).await;
+        return copied;
+    }
+    let _ = fs::remove_file(source).await;
+    Ok(())
+}
+
+// Rename that fails with AlreadyExists instead of replacing the target
+async fn rename_no_replace(source: &Path, target: &Path) -> io::Result<()> {
+    let (source, target) = (source.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        #[cfg(target_os = "linux")]
+        {
+            use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+            let path = |path: &Path| CString::new(path.as_os_str().as_bytes()).map_err(io::Error::other);
+            let (from, to) = (path(&source)?, path(&target)?);
+            // SAFETY: both paths are NUL-terminated and outlive the call
+            let renamed = unsafe {
+                libc::renameat2(libc::AT_FDCWD, from.as_ptr(), libc::AT_FDCWD, to.as_ptr(), libc::RENAME_NOREPLACE)
+            };
+            match renamed {
+                0 => return Ok(()),
+                _ => {
+                    let err = io::Error::last_os_error();
+                    // Filesystems without RENAME_NOREPLACE fall back to links
+                    if !matches!(err.raw_os_error(), Some(libc::EINVAL | libc::ENOSYS)) {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        // A hard link is never made over an existing file
+        std::fs::hard_link(&source, &target)?;
+        std::fs::remove_file(&source)
+    }).await.map_err(io::Error::other)?
+}
+
+// Finished uploads are renamed into place, which is only atomic, and only
+// possible at all, within one filesystem
+#[cfg(unix)]
+fn check_same_filesystem(dir: &Path) -> Result<(), ConfigError> {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = |path: &Path| std::fs::metadata(path)
+        .map(|metadata| metadata.dev())
+        .map_err(|err| ConfigError::Io(path.to_path_buf(), err));
+    if device(dir)? != device(Path::new("."))? {
+        return Err(ConfigError::Invalid(format!(
+            "upload staging directory {} is not on the served directory's filesystem",
+            dir.display(),
+        )));
+    }
+    Ok(())
+}
+
+// Windows offers no stable way to compare volumes; a staging directory on
+// another one makes uploads fail when they are moved into place
+#[cfg(not(unix))]
+fn check_same_filesystem(_dir: &Path) -> Result<(), ConfigError> {
+    Ok(())
+}
+
+// Bytes unprivileged processes can still write to the filesystem of `dir`
+#[cfg(unix)]
+async fn available_space(dir: &Path) -> io::Result<u64> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let dir = CString::new(dir.as_os_str().as_bytes()).map_err(io::Error::other)?;
+    tokio::task::spawn_blocking(move || {
+        // SAFETY: statvfs is plain data, filled in by the call
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: the path is NUL-terminated and outlives the call
+        match unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } {
+            // The field types differ between platforms
+            0 => Ok((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64)),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }).await.map_err(io::Error::other)?
+}
+
+#[cfg(not(unix))]
+async fn available_space(_dir: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Holes are punched in whole filesystem blocks of this size
+const BLOCK_SIZE: u64 = 4096;
+
+// Writes an upload to its file. When sparse, aligned blocks holding only
+// zeros are seeked over instead of written, so filesystems supporting
+// holes leave them unallocated
+pub struct UploadWriter<'a> {
+    file: &'a mut fs::File,
+    sparse: bool,
+    // Where the next byte goes, including zeros not written
+    position: u64,
+    // Whether zeros were skipped since the last write
+    skipped: bool,
+    // Data received past the last block boundary, which chunks split
+    // anywhere
+    pending: Vec<u8>,
+}
+
+impl<'a> UploadWriter<'a> {
+    // Write to `file`, which is positioned at `position`
+    pub fn new(file: &'a mut fs::File, position: u64, sparse: bool) -> UploadWriter<'a> {
+        UploadWriter { file, sparse, position, skipped: false, pending: Vec::new() }
+    }
+
+    pub async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        if !self.sparse {
+            return self.write_data(chunk).await;
+        }
+
+        let mut buffer = std::mem::take(&mut self.pending);
+        buffer.extend_from_slice(chunk);
+
+        // Write the data between zero blocks in as few calls as possible
+        let base = self.position;
+        let (mut start, mut offset) = (0, 0);
+        loop {
+            let end = offset + (BLOCK_SIZE - (base + offset as u64) % BLOCK_SIZE) as usize;
+            if end > buffer.len() {
+                break;
+            }
+            let block = &buffer[offset..end];
+            if block.len() as u64 == BLOCK_SIZE && block.iter().all(|&byte| byte == 0) {
+                self.write_data(&buffer[start..offset]).await?;
+                self.position += BLOCK_SIZE;
+                self.skipped = true;
+                start = end;
+            }
+            offset = end;
+        }
+        self.write_data(&buffer[start..offset]).await?;
+
+        buffer.drain(..offset);
+        self.pending = buffer;
+        Ok(())
+    }
+
+    async fn write_data(&mut self, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.skipped {
+            self.file.seek(SeekFrom::Start(self.position)).await?;
+            self.skipped = false;
+        }
+        self.file.write_all(data).await?;
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    // Write what is left, and extend the file over zeros skipped at its end
+    pub async fn finish(mut self) -> io::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        self.write_data(&pending).await?;
+        self.file.flush().await?;
+        if self.skipped {
+            self.file.set_len(self.position).await?;
+        }
+        Ok(())
+    }
+}
+
+// Reserve disk space for an upload of `length` bytes without changing the
+// file's size, so a full disk fails the upload before any data is sent.
+// Only Linux can do this; elsewhere, and on filesystems without support,
+// nothing is reserved
+pub async fn preallocate(file: &fs::File, length: u64) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::fd::AsRawFd;
+
+        let Ok(length) = libc::off_t::try_from(length) else {
+            return Ok(());
+        };
+        if length == 0 {
+            return Ok(());
+        }
+        // A handle of its own keeps the descriptor open even if the upload
+        // is abandoned while the call runs
+        let file = file.try_clone().await?.into_std().await;
+        let result = tokio::task::spawn_blocking(move || {
+            // SAFETY: `file` keeps the descriptor valid for the call
+            match unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, length) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        }).await.map_err(io::Error::other)?;
+        match result {
+            Err(err) if err.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+            result => result,
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, length);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    #[tokio::test]
+    async fn move_into_place_moves_the_upload() {
+        let dir = scratch_dir("uploads-move");
+        std::fs::write(dir.join("partial"), "data").unwrap();
+
+        move_into_place(&dir.join("partial"), &dir.join("target")).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("target")).unwrap(), "data");
+        assert!(!dir.join("partial").exists());
+    }
+
+    #[tokio::test]
+    async fn move_into_place_never_replaces_the_target() {
+        let dir = scratch_dir("uploads-conflict");
+        std::fs::write(dir.join("partial"), "upload").unwrap();
+        std::fs::write(dir.join("target"), "existing").unwrap();
+
+        let moved = move_into_place(&dir.join("partial"), &dir.join("target")).await;
+        assert!(matches!(moved, Err(AppError::Conflict(_))));
+        assert_eq!(std::fs::read_to_string(dir.join("target")).unwrap(), "existing");
+        assert!(dir.join("partial").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn move_into_place_does_not_follow_a_symlink_at_the_target() {
+        let dir = scratch_dir("uploads-symlink");
+        std::fs::write(dir.join("partial"), "upload").unwrap();
+        std::fs::write(dir.join("elsewhere"), "existing").unwrap();
+        std::os::unix::fs::symlink("elsewhere", dir.join("target")).unwrap();
+
+        let moved = move_into_place(&dir.join("partial"), &dir.join("target")).await;
+        assert!(matches!(moved, Err(AppError::Conflict(_))));
+        assert_eq!(std::fs::read_to_string(dir.join("elsewhere")).unwrap(), "existing");
+    }
+
+    #[test]
+    fn partial_files_are_recognised_by_name() {
+        assert!(is_partial(Path::new("docs/.upload-1234")));
+        assert!(!is_partial(Path::new(".upload-dir/file.txt")));
+    }
+}
@@ -0,0 +1,111 @@
+use std::{path::Path, sync::Arc};
+
+use notify::{
+    event::{ModifyKind, RenameMode},
+    Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+use crate::{events::ChangeKind, state::AppState, uploads};
+
+// Watch the served directory for changes made on disk by anything, the
+// server included, so caches never serve stale copies and event
+// subscribers hear about every change. Dropping the watcher stops it
+pub fn watch(state: Arc<AppState>) -> notify::Result<RecommendedWatcher> {
+    let root = std::fs::canonicalize(".")?;
+    let watched = root.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(event) => {
+                // Metadata changes such as chmod, touch or the ETag hash
+                // attribute being stored change what is cached, not what
+                // subscribers care about
+                if let EventKind::Modify(ModifyKind::Metadata(_)) = event.kind {
+                    for path in event.paths.iter().filter_map(|path| path.strip_prefix(&root).ok()) {
+                        state.invalidate(path);
+                    }
+                    return;
+                }
+                // Form uploads in progress are reported once moved in place
+                for (kind, path) in changes(&event) {
+                    if uploads::is_partial(path) {
+                        continue;
+                    }
+                    if let Ok(path) = path.strip_prefix(&root) {
+                        state.observed(kind, path);
+                    }
+                }
+            }
+            // Usually a full kernel queue; changes may have been missed
+            Err(err) => tracing::warn!("Watching {} failed: {}", root.display(), err),
+        }
+    })?;
+    watcher.watch(&watched, RecursiveMode::Recursive)?;
+    Ok(watcher)
+}
+
+// What an event means for each path it names
+fn changes(event: &Event) -> Vec<(ChangeKind, &Path)> {
+    let kind = match event.kind {
+        EventKind::Create(_) => ChangeKind::Create,
+        EventKind::Remove(_) => ChangeKind::Delete,
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Delete,
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Create,
+        // Reported after the From and To halves, which were handled already
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => return Vec::new(),
+        // The backend could not tell which side of a rename this is
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            return event.paths.iter()
+                .map(|path| match path.symlink_metadata() {
+                    Ok(_) => (ChangeKind::Create, path.as_path()),
+                    Err(_) => (ChangeKind::Delete, path.as_path()),
+                })
+                .collect();
+        }
+        EventKind::Modify(_) | EventKind::Any | EventKind::Other => ChangeKind::Modify,
+        EventKind::Access(_) => return Vec::new(),
+    };
+    event.paths.iter().map(|path| (kind, path.as_path())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+    use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind};
+
+    // The changes an event of `kind` naming `paths` stands for
+    fn kinds(kind: EventKind, paths: &[&Path]) -> Vec<String> {
+        let event = paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.to_path_buf()));
+        changes(&event).iter()
+            .map(|(kind, path)| format!("{:?} {}", kind, path.file_name().unwrap().to_string_lossy()))
+            .collect()
+    }
+
+    #[test]
+    fn renames_are_a_delete_and_a_create() {
+        let (from, to) = (Path::new("old.txt"), Path::new("new.txt"));
+        assert_eq!(kinds(EventKind::Modify(ModifyKind::Name(RenameMode::From)), &[from]), ["Delete old.txt"]);
+        assert_eq!(kinds(EventKind::Modify(ModifyKind::Name(RenameMode::To)), &[to]), ["Create new.txt"]);
+        assert!(kinds(EventKind::Modify(ModifyKind::Name(RenameMode::Both)), &[from, to]).is_empty());
+    }
+
+    #[test]
+    fn unknown_renames_go_by_what_is_on_disk() {
+        let dir = scratch_dir("watcher_rename");
+        std::fs::write(dir.join("there.txt"), "a").unwrap();
+        let (gone, there) = (dir.join("gone.txt"), dir.join("there.txt"));
+        assert_eq!(
+            kinds(EventKind::Modify(ModifyKind::Name(RenameMode::Any)), &[&gone, &there]),
+            ["Delete gone.txt", "Create there.txt"],
+        );
+    }
+
+    #[test]
+    fn other_events_map_to_their_kind() {
+        let path = Path::new("a.txt");
+        assert_eq!(kinds(EventKind::Create(CreateKind::File), &[path]), ["Create a.txt"]);
+        assert_eq!(kinds(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &[path]), ["Modify a.txt"]);
+        assert_eq!(kinds(EventKind::Modify(ModifyKind::Metadata(MetadataKind::Any)), &[path]), ["Modify a.txt"]);
+        assert!(kinds(EventKind::Access(AccessKind::Any), &[path]).is_empty());
+    }
+}